@@ -0,0 +1,7 @@
+fn main() {
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path().unwrap());
+    tonic_build::configure()
+        .build_client(false)
+        .compile(&["proto/chaingraph.proto"], &["proto"])
+        .unwrap();
+}
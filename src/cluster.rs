@@ -0,0 +1,375 @@
+use std::fs;
+
+use ethers::types::H160;
+use hashbrown::{HashMap, HashSet};
+use indradb::{
+    Database, Datastore, Identifier, Json, QueryExt, RocksdbDatastore, SpecificVertexQuery,
+    VertexWithPropertyPresenceQuery, VertexWithPropertyValueQuery,
+};
+use rocksdb::{Options, DB};
+use uuid::Uuid;
+
+use crate::{codec, components::UnionFind, rocks, utils};
+
+/// the vertex property which carries the cluster id
+pub const CLUSTER: &str = "cluster";
+
+/// `approve(address,uint256)`
+const APPROVE_SELECTOR: [u8; 4] = [0x09, 0x5e, 0xa7, 0xb3];
+/// `transferFrom(address,address,uint256)`
+const TRANSFER_FROM_SELECTOR: [u8; 4] = [0x23, 0xb8, 0x72, 0xdd];
+
+const BATCH_SIZE: usize = 10_000;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Heuristic {
+    /// the users of the same exchange deposit address
+    Deposit,
+    /// the airdrop participants funded by the same address
+    Airdrop,
+    /// the token approvals whose spender later calls `transferFrom` for the owner itself,
+    /// which only an externally owned address can do
+    Approval,
+}
+
+/// the addresses linked by the heuristics, numbered for the union-find
+#[derive(Default)]
+struct Links {
+    ids: HashMap<Uuid, usize>,
+    uuids: Vec<Uuid>,
+    pairs: Vec<(usize, usize)>,
+}
+
+impl Links {
+    fn index(&mut self, id: Uuid) -> usize {
+        if let Some(&i) = self.ids.get(&id) {
+            return i;
+        }
+        self.uuids.push(id);
+        self.ids.insert(id, self.uuids.len() - 1);
+        self.uuids.len() - 1
+    }
+
+    fn link(&mut self, a: Uuid, b: Uuid) {
+        if a == b {
+            return;
+        }
+        let a = self.index(a);
+        let b = self.index(b);
+        self.pairs.push((a, b));
+    }
+
+    /// the members of each cluster, keyed by the smallest member id
+    fn clusters(&self) -> HashMap<Uuid, Vec<Uuid>> {
+        let mut uf = UnionFind::new(self.uuids.len());
+        for &(a, b) in &self.pairs {
+            uf.union(a, b);
+        }
+
+        let mut roots: HashMap<usize, Vec<Uuid>> = HashMap::new();
+        for (i, id) in self.uuids.iter().enumerate() {
+            roots.entry(uf.find(i)).or_default().push(*id);
+        }
+        roots
+            .into_values()
+            .map(|members| (*members.iter().min().unwrap(), members))
+            .collect()
+    }
+}
+
+fn read_list(filename: Option<String>) -> HashSet<Uuid> {
+    match filename {
+        None => HashSet::new(),
+        Some(filename) => fs::read_to_string(filename)
+            .unwrap()
+            .split_whitespace()
+            .map(utils::addr_to_uuid)
+            .collect(),
+    }
+}
+
+/// the first address argument of the call, i.e. the spender of `approve`
+/// and the owner of `transferFrom`
+fn address_arg(input: &[u8], selector: [u8; 4]) -> Option<H160> {
+    if input.len() < 36 || input[..4] != selector {
+        return None;
+    }
+    Some(H160::from_slice(&input[16..36]))
+}
+
+/// run the heuristics over all edge details, two passes at most
+fn link(
+    db: &DB,
+    heuristics: &[Heuristic],
+    exchanges: &HashSet<Uuid>,
+    airdrops: &HashSet<Uuid>,
+    deposit_ratio: f64,
+) -> Links {
+    let mut links = Links::default();
+
+    let mut contracts: HashSet<Uuid> = HashSet::new();
+    let mut to_exchange: HashMap<Uuid, u64> = HashMap::new();
+    let mut participants: HashSet<Uuid> = HashSet::new();
+    // the earliest approval and the latest spending block of each (owner, spender)
+    let mut approvals: HashMap<(Uuid, Uuid), u64> = HashMap::new();
+    let mut spendings: HashMap<(Uuid, Uuid), u64> = HashMap::new();
+
+    rocks::for_each_edge_property(db, "details", |out_id, _, in_id, value| {
+        let tx = match codec::decode_or_skip(&value) {
+            Some(tx) => tx,
+            None => return,
+        };
+        if tx.is_create {
+            contracts.insert(in_id);
+        }
+        if exchanges.contains(&in_id) && !exchanges.contains(&out_id) {
+            *to_exchange.entry(out_id).or_default() += 1;
+        }
+        if airdrops.contains(&in_id) {
+            participants.insert(out_id);
+        }
+        // the reverted calls approve and move nothing
+        let succeeded = tx.status.map_or(true, |status| !status.is_zero());
+        if heuristics.contains(&Heuristic::Approval) && succeeded {
+            if let Some(block) = tx.block_number.map(|n| n.as_u64()) {
+                let input = tx.input.as_ref();
+                if let Some(spender) = address_arg(input, APPROVE_SELECTOR) {
+                    let block_of = approvals
+                        .entry((out_id, utils::h160_to_uuid(&spender)))
+                        .or_insert(block);
+                    *block_of = block.min(*block_of);
+                }
+                if let Some(owner) = address_arg(input, TRANSFER_FROM_SELECTOR) {
+                    let block_of = spendings
+                        .entry((utils::h160_to_uuid(&owner), out_id))
+                        .or_insert(block);
+                    *block_of = block.max(*block_of);
+                }
+            }
+        }
+    });
+    log::warn!("{} contracts", contracts.len());
+
+    // a deposit address forwards (almost) everything it receives to the exchange
+    let mut deposits: HashSet<Uuid> = HashSet::new();
+    if heuristics.contains(&Heuristic::Deposit) {
+        for (id, n) in to_exchange {
+            let out_degree = rocks::count_edge_ranges(db, "edge_ranges:v2", id);
+            if n as f64 / out_degree as f64 >= deposit_ratio {
+                deposits.insert(id);
+            }
+        }
+        log::warn!("{} deposit addresses", deposits.len());
+    }
+
+    if !heuristics.contains(&Heuristic::Airdrop) {
+        participants.clear();
+    }
+    log::warn!("{} airdrop participants", participants.len());
+
+    // the earliest funding of each participant
+    let mut fundings: HashMap<Uuid, (u64, Uuid)> = HashMap::new();
+    if !deposits.is_empty() || !participants.is_empty() {
+        rocks::for_each_edge_property(db, "details", |out_id, _, in_id, value| {
+            if exchanges.contains(&out_id) || contracts.contains(&out_id) {
+                return;
+            }
+            if deposits.contains(&in_id) {
+                links.link(out_id, in_id);
+            }
+            if participants.contains(&in_id) {
+                let block = match codec::decode_or_skip(&value).and_then(|tx| tx.block_number) {
+                    Some(block) => block.as_u64(),
+                    None => return,
+                };
+                let funding = fundings.entry(in_id).or_insert((block, out_id));
+                if block < funding.0 {
+                    *funding = (block, out_id);
+                }
+            }
+        });
+    }
+
+    // a single funded participant is just a payment, not a farm
+    let mut farms: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+    for (participant, (_, funder)) in fundings {
+        farms.entry(funder).or_default().push(participant);
+    }
+    for (funder, farm) in farms {
+        if farm.len() > 1 {
+            for participant in farm {
+                links.link(funder, participant);
+            }
+        }
+    }
+
+    // a contract spender, e.g. a router, only calls `transferFrom` inside the txs of others
+    for ((owner, spender), approved) in approvals {
+        if spendings
+            .get(&(owner, spender))
+            .map_or(false, |&spent| spent >= approved)
+        {
+            links.link(owner, spender);
+        }
+    }
+
+    links
+}
+
+/// the address of the smallest member with a vertex, every linked address has sent or received
+/// a tx, so the uuid of the smallest member is only a fallback
+fn cluster_name(db: &DB, members: &[Uuid]) -> String {
+    let mut members = members.to_vec();
+    members.sort();
+    members
+        .iter()
+        .find_map(|id| rocks::get_vertex(db, *id))
+        .unwrap_or_else(|| members[0].to_string())
+}
+
+/// drop the clusters of the previous run, otherwise the vertices no longer clustered keep theirs
+fn clear_clusters(db: &Database<RocksdbDatastore>) {
+    let name = Identifier::new(CLUSTER).unwrap();
+    let q = VertexWithPropertyPresenceQuery::new(name)
+        .properties()
+        .unwrap()
+        .name(name);
+    match db.delete(q) {
+        Ok(()) => log::warn!("the previous clusters cleared"),
+        // never indexed, i.e. never clustered
+        Err(indradb::Error::NotIndexed) => {}
+        Err(e) => panic!("{:?}", e),
+    }
+}
+
+pub fn gen_clusters(
+    path: String,
+    opts: &mut Options,
+    heuristics: Vec<Heuristic>,
+    exchanges: Option<String>,
+    airdrops: Option<String>,
+    deposit_ratio: f64,
+    output: Option<String>,
+) {
+    let exchanges = read_list(exchanges);
+    let airdrops = read_list(airdrops);
+    if heuristics.contains(&Heuristic::Deposit) && exchanges.is_empty() {
+        log::warn!("no exchange address, the deposit heuristic finds nothing");
+    }
+    if heuristics.contains(&Heuristic::Airdrop) && airdrops.is_empty() {
+        log::warn!("no airdrop address, the airdrop heuristic finds nothing");
+    }
+
+    let raw = rocks::open_read_only(&path, opts);
+    let links = link(&raw, &heuristics, &exchanges, &airdrops, deposit_ratio);
+    let clusters = links.clusters();
+    log::warn!("{} clusters", clusters.len());
+
+    let names: HashMap<Uuid, String> = clusters
+        .iter()
+        .map(|(id, members)| (*id, cluster_name(&raw, members)))
+        .collect();
+    drop(raw);
+
+    let db = RocksdbDatastore::new_db_with_options(path, opts).unwrap();
+    let name = Identifier::new(CLUSTER).unwrap();
+    clear_clusters(&db);
+    let mut output = output.map(|output| {
+        let mut output = csv::Writer::from_path(output).unwrap();
+        output.write_record(["address", "cluster"]).unwrap();
+        output
+    });
+
+    for (id, members) in &clusters {
+        let cluster = &names[id];
+        db.set_properties(
+            SpecificVertexQuery::new(members.clone()),
+            name,
+            &Json::new(serde_json::Value::from(cluster.as_str())),
+        )
+        .unwrap();
+
+        if let Some(output) = output.as_mut() {
+            let q = SpecificVertexQuery::new(members.clone());
+            for v in indradb::util::extract_vertices(db.get(q).unwrap()).unwrap() {
+                output.write_record([v.t.as_str(), cluster]).unwrap();
+            }
+        }
+    }
+    if let Some(output) = output.as_mut() {
+        output.flush().unwrap();
+    }
+
+    db.index_property(name).unwrap();
+}
+
+/// the cluster ids of the vertices, the unclustered ones are absent
+pub fn get_clusters<D: Datastore>(db: &Database<D>, ids: Vec<Uuid>) -> HashMap<Uuid, String> {
+    let mut clusters = HashMap::new();
+    for chunk in ids.chunks(BATCH_SIZE) {
+        let q = SpecificVertexQuery::new(chunk.to_vec())
+            .properties()
+            .unwrap()
+            .name(Identifier::new(CLUSTER).unwrap());
+        let properties = indradb::util::extract_vertex_properties(db.get(q).unwrap()).unwrap();
+        for v_props in properties {
+            if let Some(cluster) = v_props.props[0].value.0.as_str() {
+                clusters.insert(v_props.vertex.id, cluster.to_owned());
+            }
+        }
+    }
+    clusters
+}
+
+/// all vertices in the same cluster, or the vertex itself when unclustered
+pub fn members<D: Datastore>(db: &Database<D>, id: Uuid) -> Vec<Uuid> {
+    match get_clusters(db, vec![id]).remove(&id) {
+        None => vec![id],
+        Some(cluster) => {
+            let q = VertexWithPropertyValueQuery::new(
+                Identifier::new(CLUSTER).unwrap(),
+                Json::new(serde_json::Value::from(cluster)),
+            );
+            indradb::util::extract_vertices(db.get(q).unwrap())
+                .unwrap()
+                .into_iter()
+                .map(|v| v.id)
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_address_arg() {
+        let spender = H160::from_low_u64_be(0xbeef);
+        let mut input = APPROVE_SELECTOR.to_vec();
+        input.extend([0u8; 12]);
+        input.extend(spender.as_bytes());
+        input.extend([0xffu8; 32]);
+        assert_eq!(address_arg(&input, APPROVE_SELECTOR), Some(spender));
+        assert_eq!(address_arg(&input, TRANSFER_FROM_SELECTOR), None);
+
+        input[0] = 0xa9; // transfer
+        assert_eq!(address_arg(&input, APPROVE_SELECTOR), None);
+        assert_eq!(address_arg(&APPROVE_SELECTOR, APPROVE_SELECTOR), None);
+    }
+
+    #[test]
+    fn test_clusters() {
+        let ids: Vec<Uuid> = (1..=5u8).map(|i| Uuid::from_bytes([i; 16])).collect();
+        let mut links = Links::default();
+        links.link(ids[1], ids[0]);
+        links.link(ids[2], ids[1]);
+        links.link(ids[4], ids[3]);
+        links.link(ids[4], ids[4]);
+
+        let clusters = links.clusters();
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters[&ids[0]].len(), 3);
+        assert_eq!(clusters[&ids[3]].len(), 2);
+    }
+}
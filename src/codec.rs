@@ -0,0 +1,323 @@
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
+use bincode::Options as _;
+use ethers::types::{Address, Bytes, H256, U256, U64};
+use indradb::{Identifier, Json, QueryExt, RangeVertexQuery, RocksdbDatastore};
+use rocksdb::{Options, DB};
+use uuid::Uuid;
+
+use crate::{eth_common::TransactionInfo, rocks};
+
+/// `details` stored as the plain json object
+pub const FORMAT_JSON: u64 = 1;
+/// `details` stored as the base64 string of the varint bincode encoded `CompactTransactionInfo`
+pub const FORMAT_COMPACT: u64 = 2;
+
+/// the key of the details format in `metadata:v2`, beside the indradb metadata
+const FORMAT_KEY: &[u8] = b"chaingraph:details_format";
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Json,
+    Compact,
+}
+
+impl Format {
+    pub fn version(self) -> u64 {
+        match self {
+            Format::Json => FORMAT_JSON,
+            Format::Compact => FORMAT_COMPACT,
+        }
+    }
+}
+
+/// the format version of the `details` property, the databases without marker are json
+pub fn read_format(db: &DB) -> u64 {
+    stored_format(db).unwrap_or(FORMAT_JSON)
+}
+
+/// the format marker, None when it was never written
+pub fn stored_format(db: &DB) -> Option<u64> {
+    let cf = db.cf_handle("metadata:v2").unwrap();
+    db.get_cf(cf, FORMAT_KEY)
+        .unwrap()
+        .map(|value| serde_json::from_slice(&value).unwrap())
+}
+
+/// `read_format` beside the writer holding the datastore
+pub fn get_format(path: &str, opts: &Options) -> u64 {
+    read_format(&rocks::open_read_only(path, opts))
+}
+
+pub fn write_format(db: &DB, format: u64) {
+    let cf = db.cf_handle("metadata:v2").unwrap();
+    db.put_cf(cf, FORMAT_KEY, serde_json::to_vec(&format).unwrap())
+        .unwrap();
+}
+
+/// fixed layout copy of `TransactionInfo`, no hex string inside
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+struct CompactTransactionInfo {
+    hash: [u8; 32],
+    nonce: [u64; 4],
+    block_hash: Option<[u8; 32]>,
+    block_number: Option<u64>,
+    block_timestamp: Option<u64>,
+    transaction_index: Option<u64>,
+    from: [u8; 20],
+    to: Option<[u8; 20]>,
+    value: [u64; 4],
+    gas_price: Option<[u64; 4]>,
+    gas: [u64; 4],
+    input: Vec<u8>,
+    v: u64,
+    r: [u64; 4],
+    s: [u64; 4],
+    transaction_type: Option<u64>,
+    max_priority_fee_per_gas: Option<[u64; 4]>,
+    max_fee_per_gas: Option<[u64; 4]>,
+    cumulative_gas_used: [u64; 4],
+    gas_used: Option<[u64; 4]>,
+    is_create: bool,
+    status: Option<u64>,
+    effective_gas_price: Option<[u64; 4]>,
+}
+
+impl From<&TransactionInfo> for CompactTransactionInfo {
+    fn from(tx: &TransactionInfo) -> Self {
+        CompactTransactionInfo {
+            hash: tx.hash.0,
+            nonce: tx.nonce.0,
+            block_hash: tx.block_hash.map(|h| h.0),
+            block_number: tx.block_number.map(|n| n.as_u64()),
+            block_timestamp: tx.block_timestamp.map(|n| n.as_u64()),
+            transaction_index: tx.transaction_index.map(|n| n.as_u64()),
+            from: tx.from.0,
+            to: tx.to.map(|a| a.0),
+            value: tx.value.0,
+            gas_price: tx.gas_price.map(|n| n.0),
+            gas: tx.gas.0,
+            input: tx.input.to_vec(),
+            v: tx.v.as_u64(),
+            r: tx.r.0,
+            s: tx.s.0,
+            transaction_type: tx.transaction_type.map(|n| n.as_u64()),
+            max_priority_fee_per_gas: tx.max_priority_fee_per_gas.map(|n| n.0),
+            max_fee_per_gas: tx.max_fee_per_gas.map(|n| n.0),
+            cumulative_gas_used: tx.cumulative_gas_used.0,
+            gas_used: tx.gas_used.map(|n| n.0),
+            is_create: tx.is_create,
+            status: tx.status.map(|n| n.as_u64()),
+            effective_gas_price: tx.effective_gas_price.map(|n| n.0),
+        }
+    }
+}
+
+impl From<CompactTransactionInfo> for TransactionInfo {
+    fn from(tx: CompactTransactionInfo) -> Self {
+        TransactionInfo {
+            hash: H256(tx.hash),
+            nonce: U256(tx.nonce),
+            block_hash: tx.block_hash.map(H256),
+            block_number: tx.block_number.map(U64::from),
+            block_timestamp: tx.block_timestamp.map(U64::from),
+            transaction_index: tx.transaction_index.map(U64::from),
+            from: Address::from(tx.from),
+            to: tx.to.map(Address::from),
+            value: U256(tx.value),
+            gas_price: tx.gas_price.map(U256),
+            gas: U256(tx.gas),
+            input: Bytes::from(tx.input),
+            v: U64::from(tx.v),
+            r: U256(tx.r),
+            s: U256(tx.s),
+            transaction_type: tx.transaction_type.map(U64::from),
+            max_priority_fee_per_gas: tx.max_priority_fee_per_gas.map(U256),
+            max_fee_per_gas: tx.max_fee_per_gas.map(U256),
+            cumulative_gas_used: U256(tx.cumulative_gas_used),
+            gas_used: tx.gas_used.map(U256),
+            is_create: tx.is_create,
+            status: tx.status.map(U64::from),
+            effective_gas_price: tx.effective_gas_price.map(U256),
+        }
+    }
+}
+
+/// varint integers, so the mostly small limbs of the u256 take a byte each
+fn varint() -> impl bincode::Options {
+    bincode::DefaultOptions::new()
+}
+
+/// the `details` property of the transaction in the format
+pub fn encode_details(tx: &TransactionInfo, format: u64) -> Result<Json, String> {
+    let value = match format {
+        FORMAT_COMPACT => {
+            let bin = varint()
+                .serialize(&CompactTransactionInfo::from(tx))
+                .map_err(|e| e.to_string())?;
+            serde_json::Value::String(STANDARD_NO_PAD.encode(bin))
+        }
+        FORMAT_JSON => serde_json::to_value(tx).map_err(|e| e.to_string())?,
+        _ => return Err(format!("details format {} is not writable", format)),
+    };
+    Ok(Json::new(value))
+}
+
+/// decode by the shape of the value, so the half converted databases are still readable
+pub fn decode_details(val: &serde_json::Value) -> Result<TransactionInfo, String> {
+    match val {
+        serde_json::Value::String(s) => {
+            let bin = STANDARD_NO_PAD.decode(s).map_err(|e| e.to_string())?;
+            let compact: CompactTransactionInfo =
+                varint().deserialize(&bin).map_err(|e| e.to_string())?;
+            Ok(compact.into())
+        }
+        _ => serde_json::from_value(val.clone()).map_err(|e| e.to_string()),
+    }
+}
+
+/// `decode_details` for the scans, a malformed value is logged and skipped like an edge without details
+pub fn decode_or_skip(val: &serde_json::Value) -> Option<TransactionInfo> {
+    match decode_details(val) {
+        Ok(tx) => Some(tx),
+        Err(e) => {
+            log::warn!("skip the malformed details: {}", e);
+            None
+        }
+    }
+}
+
+/// the vertex count of a page when converting
+const PAGE_SIZE: u32 = 1_000;
+
+/// rewrite the `details` of all edges into the format, in place
+pub fn convert(path: String, opts: &mut Options, format: Format) {
+    let format = format.version();
+    log::warn!(
+        "convert from format {} to {}",
+        get_format(&path, opts),
+        format
+    );
+    let db = RocksdbDatastore::new_db_with_options(&path, opts).unwrap();
+
+    let mut skipped = 0usize;
+    let details = Identifier::new("details").unwrap();
+    let mut start_id: Option<Uuid> = None;
+    let mut converted = 0usize;
+
+    loop {
+        let mut q = RangeVertexQuery::new().limit(PAGE_SIZE);
+        if let Some(id) = start_id {
+            q = q.start_id(id);
+        }
+        let vertices = indradb::util::extract_vertices(db.get(q.clone()).unwrap()).unwrap();
+        if vertices.is_empty() {
+            break;
+        }
+
+        let out_q = q.outbound().unwrap().properties().unwrap().name(details);
+        let properties = indradb::util::extract_edge_properties(db.get(out_q).unwrap()).unwrap();
+
+        let mut items = Vec::with_capacity(properties.len());
+        for e_props in properties {
+            let tx = match decode_or_skip(e_props.props[0].value.0.as_ref()) {
+                Some(tx) => tx,
+                None => {
+                    skipped += 1;
+                    continue;
+                }
+            };
+            items.push(indradb::BulkInsertItem::EdgeProperty(
+                e_props.edge,
+                details,
+                encode_details(&tx, format).unwrap(),
+            ));
+        }
+        converted += items.len();
+        db.bulk_insert(items).unwrap();
+        log::warn!("converted {} edges", converted);
+
+        let last = vertices.last().unwrap().id;
+        start_id = Some(indradb::util::next_uuid(last).unwrap());
+    }
+
+    if skipped > 0 {
+        log::warn!("{} malformed details are left as they are", skipped);
+    }
+    db.sync().unwrap();
+    drop(db);
+
+    write_format(&DB::open_cf(opts, &path, rocks::CF_NAMES).unwrap(), format);
+    log::warn!("everything done");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::utils::hex;
+
+    #[test]
+    fn test_roundtrip() {
+        let tx = TransactionInfo {
+            hash: H256::repeat_byte(0x11),
+            nonce: U256::from(7),
+            block_number: Some(U64::from(16_800_000)),
+            block_timestamp: Some(U64::from(1_678_000_000)),
+            from: Address::repeat_byte(0x22),
+            to: None,
+            value: U256::MAX,
+            gas: U256::from(21_000),
+            input: Bytes::from(vec![0xde, 0xad]),
+            gas_used: Some(U256::from(21_000)),
+            is_create: true,
+            ..Default::default()
+        };
+
+        let json = encode_details(&tx, FORMAT_JSON).unwrap();
+        assert_eq!(decode_details(json.0.as_ref()), Ok(tx.clone()));
+
+        let compact = encode_details(&tx, FORMAT_COMPACT).unwrap();
+        assert!(compact.0.is_string());
+        assert_eq!(decode_details(compact.0.as_ref()), Ok(tx));
+
+        assert!(decode_details(&serde_json::Value::from("not base64")).is_err());
+        assert!(decode_details(&serde_json::json!({"hash": 1})).is_err());
+        assert!(encode_details(&TransactionInfo::default(), 0).is_err());
+    }
+
+    #[test]
+    fn test_compact_size() {
+        // a plain transfer
+        let tx = TransactionInfo {
+            hash: H256::repeat_byte(0x11),
+            nonce: U256::from(1_024),
+            block_hash: Some(H256::repeat_byte(0x33)),
+            block_number: Some(U64::from(16_800_000)),
+            block_timestamp: Some(U64::from(1_678_000_000)),
+            transaction_index: Some(U64::from(42)),
+            from: Address::repeat_byte(0x22),
+            to: Some(Address::repeat_byte(0x44)),
+            value: U256::exp10(18),
+            gas_price: Some(U256::from(30_000_000_000u64)),
+            gas: U256::from(21_000),
+            r: U256::MAX,
+            s: U256::MAX,
+            transaction_type: Some(U64::from(2)),
+            cumulative_gas_used: U256::from(1_234_567),
+            gas_used: Some(U256::from(21_000)),
+            status: Some(U64::from(1)),
+            effective_gas_price: Some(U256::from(30_000_000_000u64)),
+            ..Default::default()
+        };
+
+        let size = |format| {
+            serde_json::to_string(encode_details(&tx, format).unwrap().0.as_ref())
+                .unwrap()
+                .len()
+        };
+        let (json, compact) = (size(FORMAT_JSON), size(FORMAT_COMPACT));
+        // the fixed width bincode in hex, i.e. without the varint and the base64
+        let fixed = hex::encode(bincode::serialize(&CompactTransactionInfo::from(&tx)).unwrap());
+        assert!(compact * 2 < json, "{} vs {}", compact, json);
+        assert!(compact * 2 < fixed.len(), "{} vs {}", compact, fixed.len());
+    }
+}
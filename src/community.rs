@@ -0,0 +1,236 @@
+use std::collections::BTreeMap;
+
+use hashbrown::HashMap;
+use indradb::{Database, Datastore, Edge};
+
+use crate::{feature, subgraph::Subgraph, utils};
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Weight {
+    /// every connected pair weighs 1
+    None,
+    /// the transaction count between the pair
+    Count,
+    /// the transferred ether between the pair
+    Value,
+}
+
+/// the undirected adjacency, a self loop is stored once with the doubled weight
+type Adjacency = Vec<Vec<(usize, f64)>>;
+
+fn adjacency(n: usize, edges: &[(usize, usize, f64)]) -> Adjacency {
+    let mut pairs: BTreeMap<(usize, usize), f64> = BTreeMap::new();
+    for &(a, b, w) in edges {
+        if a == b {
+            *pairs.entry((a, a)).or_default() += 2. * w;
+        } else {
+            *pairs.entry((a, b)).or_default() += w;
+            *pairs.entry((b, a)).or_default() += w;
+        }
+    }
+
+    let mut adj = vec![Vec::new(); n];
+    for ((a, b), w) in pairs {
+        adj[a].push((b, w));
+    }
+    adj
+}
+
+/// move the nodes between the communities until the modularity stops improving
+fn one_level(adj: &Adjacency) -> (Vec<usize>, bool) {
+    let n = adj.len();
+    let k: Vec<f64> = adj.iter().map(|a| a.iter().map(|(_, w)| w).sum()).collect();
+    let m2: f64 = k.iter().sum();
+    let mut comm: Vec<usize> = (0..n).collect();
+    if m2 == 0. {
+        return (comm, false);
+    }
+
+    let mut tot = k.clone();
+    let mut improved = false;
+    loop {
+        let mut moved = 0;
+        for i in 0..n {
+            let ci = comm[i];
+            let mut weights: BTreeMap<usize, f64> = BTreeMap::new();
+            for &(j, w) in &adj[i] {
+                if j != i {
+                    *weights.entry(comm[j]).or_default() += w;
+                }
+            }
+
+            tot[ci] -= k[i];
+            let mut best = ci;
+            let mut best_gain = weights.get(&ci).copied().unwrap_or(0.) - tot[ci] * k[i] / m2;
+            for (&c, &w) in &weights {
+                let gain = w - tot[c] * k[i] / m2;
+                if gain > best_gain + 1e-12 {
+                    best = c;
+                    best_gain = gain;
+                }
+            }
+            tot[best] += k[i];
+            comm[i] = best;
+
+            if best != ci {
+                moved += 1;
+            }
+        }
+
+        if moved == 0 {
+            break;
+        }
+        improved = true;
+    }
+
+    (comm, improved)
+}
+
+/// the communities as the nodes of the next level
+fn aggregate(adj: &Adjacency, comm: &[usize], count: usize) -> Adjacency {
+    let mut pairs: BTreeMap<(usize, usize), f64> = BTreeMap::new();
+    for (i, a) in adj.iter().enumerate() {
+        for &(j, w) in a {
+            *pairs.entry((comm[i], comm[j])).or_default() += w;
+        }
+    }
+
+    let mut next = vec![Vec::new(); count];
+    for ((a, b), w) in pairs {
+        next[a].push((b, w));
+    }
+    next
+}
+
+/// renumber the communities from 0 in the order of the first member
+fn renumber(comm: &mut [usize]) -> usize {
+    let mut ids: HashMap<usize, usize> = HashMap::new();
+    for c in comm.iter_mut() {
+        let next = ids.len();
+        *c = *ids.entry(*c).or_insert(next);
+    }
+    ids.len()
+}
+
+/// louvain on the undirected view of the edges, returns the community of each node
+pub fn louvain(n: usize, edges: &[(usize, usize, f64)]) -> Vec<usize> {
+    let mut adj = adjacency(n, edges);
+    let mut membership: Vec<usize> = (0..n).collect();
+
+    loop {
+        let (mut level, improved) = one_level(&adj);
+        if !improved {
+            break;
+        }
+        let count = renumber(&mut level);
+        for c in membership.iter_mut() {
+            *c = level[*c];
+        }
+        adj = aggregate(&adj, &level, count);
+    }
+
+    renumber(&mut membership);
+    membership
+}
+
+/// the community of each address in the crawled subgraph
+pub fn detect<D: Datastore>(
+    db: &Database<D>,
+    subgraph: &Subgraph,
+    weight: Weight,
+) -> HashMap<String, usize> {
+    // the collapsed clusters share the name, so the nodes are keyed by name,
+    // and sorted to keep the result stable
+    let mut names: Vec<&str> = subgraph.vertices.values().map(|t| t.as_str()).collect();
+    names.sort();
+    names.dedup();
+    let index: HashMap<&str, usize> = names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| (*name, i))
+        .collect();
+    let pair = |e: &Edge| {
+        (
+            index[subgraph.address(&e.outbound_id)],
+            index[subgraph.address(&e.inbound_id)],
+        )
+    };
+
+    let edges: Vec<(usize, usize, f64)> = match weight {
+        Weight::None | Weight::Count => subgraph
+            .edges
+            .iter()
+            .map(|e| {
+                let (from, to) = pair(e);
+                (from, to, 1.)
+            })
+            .collect(),
+        Weight::Value => feature::fetch_edge_details(db, subgraph.edges.clone())
+            .into_iter()
+            .map(|(e, tx)| {
+                let (from, to) = pair(&e);
+                (
+                    from,
+                    to,
+                    utils::decimal_to_f64(&utils::wei_to_ether(tx.value)),
+                )
+            })
+            .collect(),
+    };
+    let edges = if weight == Weight::None {
+        let mut pairs: Vec<(usize, usize)> = edges
+            .iter()
+            .map(|&(a, b, _)| (a.min(b), a.max(b)))
+            .collect();
+        pairs.sort();
+        pairs.dedup();
+        pairs.into_iter().map(|(a, b)| (a, b, 1.)).collect()
+    } else {
+        edges
+    };
+
+    let membership = louvain(names.len(), &edges);
+    log::debug!(
+        "{} communities",
+        membership.iter().max().map_or(0, |c| c + 1)
+    );
+
+    names
+        .into_iter()
+        .zip(membership)
+        .map(|(name, c)| (name.to_owned(), c))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_louvain() {
+        // two triangles joined by a bridge
+        let edges = [
+            (0, 1, 1.),
+            (1, 2, 1.),
+            (2, 0, 1.),
+            (3, 4, 1.),
+            (4, 5, 1.),
+            (5, 3, 1.),
+            (2, 3, 1.),
+        ];
+        let comm = louvain(6, &edges);
+        assert_eq!(comm[0], comm[1]);
+        assert_eq!(comm[1], comm[2]);
+        assert_eq!(comm[3], comm[4]);
+        assert_eq!(comm[4], comm[5]);
+        assert_ne!(comm[0], comm[3]);
+
+        // a heavy bridge pulls its ends together
+        let mut heavy = edges.to_vec();
+        heavy[6].2 = 100.;
+        let comm = louvain(6, &heavy);
+        assert_eq!(comm[2], comm[3]);
+
+        assert_eq!(louvain(2, &[]), vec![0, 1]);
+    }
+}
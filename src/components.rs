@@ -0,0 +1,127 @@
+use rocksdb::Options;
+
+use crate::graph::{self, Graph};
+
+/// u32 is enough for the vertex count of the chain and halves the memory
+const NONE: u32 = u32::MAX;
+
+/// union-find with path halving and union by size
+pub struct UnionFind {
+    parent: Vec<u32>,
+    size: Vec<u32>,
+}
+
+impl UnionFind {
+    pub fn new(n: usize) -> Self {
+        assert!(n < NONE as usize, "too many vertices: {}", n);
+        UnionFind {
+            parent: (0..n as u32).collect(),
+            size: vec![1; n],
+        }
+    }
+
+    pub fn find(&mut self, mut x: usize) -> usize {
+        while self.parent[x] as usize != x {
+            let grandparent = self.parent[self.parent[x] as usize];
+            self.parent[x] = grandparent;
+            x = grandparent as usize;
+        }
+        x
+    }
+
+    pub fn union(&mut self, a: usize, b: usize) {
+        let (mut a, mut b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+        if self.size[a] < self.size[b] {
+            std::mem::swap(&mut a, &mut b);
+        }
+        self.parent[b] = a as u32;
+        self.size[a] += self.size[b];
+    }
+
+    pub fn size(&mut self, x: usize) -> usize {
+        let root = self.find(x);
+        self.size[root] as usize
+    }
+}
+
+/// the weakly connected components, and whether each vertex has any edge
+pub fn components(g: &dyn Graph) -> (UnionFind, Vec<bool>) {
+    let mut uf = UnionFind::new(g.len());
+    let mut touched = vec![false; g.len()];
+    g.for_each_edge(&mut |from, to| {
+        touched[from] = true;
+        touched[to] = true;
+        uf.union(from, to);
+    });
+    (uf, touched)
+}
+
+pub fn gen_components(
+    path: String,
+    opts: &Options,
+    edgelist: Option<String>,
+    index: String,
+    from_block: Option<u64>,
+    to_block: Option<u64>,
+    output: String,
+    min_size: usize,
+) {
+    let g = graph::open(path, opts, edgelist, &index, from_block, to_block);
+    let (mut uf, touched) = components(g.as_ref());
+
+    // the component ids are numbered in the order of the first member
+    let mut labels = vec![NONE; g.len()];
+    let mut next_label = 0u32;
+
+    let mut output = csv::Writer::from_path(output).unwrap();
+    output
+        .write_record(["address", "component_id", "component_size"])
+        .unwrap();
+    for i in 0..g.len() {
+        if !touched[i] {
+            continue;
+        }
+        let size = uf.size(i);
+        if size < min_size {
+            continue;
+        }
+
+        let root = uf.find(i);
+        if labels[root] == NONE {
+            labels[root] = next_label;
+            next_label += 1;
+        }
+        output
+            .write_record([g.name(i), labels[root].to_string(), size.to_string()])
+            .unwrap();
+    }
+    output.flush().unwrap();
+
+    log::warn!("{} components", next_label);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{graph::MemGraph, subgraph::VType};
+
+    #[test]
+    fn test_components() {
+        let mut g = MemGraph::default();
+        for (from, to) in [("a", "b"), ("c", "b"), ("d", "e"), ("e", "d"), ("f", "f")] {
+            g.add_edge(from, to);
+        }
+        let (mut uf, touched) = components(&g);
+        assert!(touched.iter().all(|&t| t));
+
+        let i = |addr| g.find(addr, VType::String).unwrap();
+        assert_eq!(uf.find(i("a")), uf.find(i("c")));
+        assert_ne!(uf.find(i("a")), uf.find(i("d")));
+        assert_eq!(uf.size(i("b")), 3);
+        assert_eq!(uf.size(i("e")), 2);
+        assert_eq!(uf.size(i("f")), 1);
+    }
+}
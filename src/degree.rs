@@ -0,0 +1,153 @@
+use indradb::{
+    CountQueryExt, Database, Datastore, Identifier, QueryExt, QueryOutputValue, SpecificVertexQuery,
+};
+use rocksdb::Options;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    codec,
+    store::{self, Store},
+    subgraph::{self, VType},
+};
+
+#[derive(Serialize, Debug, Default, Clone, Copy, PartialEq)]
+pub struct Degree {
+    pub in_degree: u64,
+    pub out_degree: u64,
+    pub degree: u64,
+}
+
+/// count the edge ranges, no edge nor property is materialised
+pub fn degree<D: Datastore>(db: &Database<D>, id: Uuid) -> Degree {
+    let out_degree = out_degree(db, id);
+    let in_degree = in_degree(db, id);
+
+    Degree {
+        in_degree,
+        out_degree,
+        degree: in_degree + out_degree,
+    }
+}
+
+/// the degree counted on the edges whose block number is in [from_block, to_block]
+pub fn degree_in_blocks<D: Datastore>(
+    db: &Database<D>,
+    id: Uuid,
+    from_block: u64,
+    to_block: u64,
+) -> Degree {
+    let out_degree = count_in_blocks(
+        db,
+        SpecificVertexQuery::single(id).outbound().unwrap(),
+        from_block,
+        to_block,
+    );
+    let in_degree = count_in_blocks(
+        db,
+        SpecificVertexQuery::single(id).inbound().unwrap(),
+        from_block,
+        to_block,
+    );
+
+    Degree {
+        in_degree,
+        out_degree,
+        degree: in_degree + out_degree,
+    }
+}
+
+pub fn out_degree<D: Datastore>(db: &Database<D>, id: Uuid) -> u64 {
+    count(db, SpecificVertexQuery::single(id).outbound().unwrap())
+}
+
+pub fn in_degree<D: Datastore>(db: &Database<D>, id: Uuid) -> u64 {
+    count(db, SpecificVertexQuery::single(id).inbound().unwrap())
+}
+
+pub fn count<D: Datastore, Q: CountQueryExt>(db: &Database<D>, q: Q) -> u64 {
+    match db.get(q.count().unwrap()).unwrap()[0] {
+        QueryOutputValue::Count(count) => count,
+        _ => unreachable!(),
+    }
+}
+
+fn count_in_blocks<D: Datastore, Q: QueryExt>(
+    db: &Database<D>,
+    q: Q,
+    from_block: u64,
+    to_block: u64,
+) -> u64 {
+    let q = q
+        .properties()
+        .unwrap()
+        .name(Identifier::new("details").unwrap());
+    let properties = indradb::util::extract_edge_properties(db.get(q).unwrap()).unwrap();
+
+    // the edges loaded from an edge list have no details, so no block number either
+    properties
+        .iter()
+        .filter_map(|e_props| e_props.props.first())
+        .filter_map(|prop| codec::decode_or_skip(prop.value.0.as_ref())?.block_number)
+        .filter(|n| (from_block..=to_block).contains(&n.as_u64()))
+        .count() as u64
+}
+
+pub fn gen_degrees(
+    path: String,
+    opts: &mut Options,
+    mode: store::OpenMode,
+    v: &mut Vec<String>,
+    output: String,
+    v_type: VType,
+    from_block: Option<u64>,
+    to_block: Option<u64>,
+) {
+    match store::open(path, opts, mode) {
+        Store::Primary(db) => write_degrees(&db, v, output, v_type, from_block, to_block),
+        Store::ReadOnly(db, _) => write_degrees(&db, v, output, v_type, from_block, to_block),
+    }
+}
+
+fn write_degrees<D: Datastore>(
+    datastore: &Database<D>,
+    v: &mut Vec<String>,
+    output: String,
+    v_type: VType,
+    from_block: Option<u64>,
+    to_block: Option<u64>,
+) {
+    v.sort();
+    v.dedup();
+    log::debug!("{} addresses", v.len());
+
+    let mut output = csv::Writer::from_path(output).unwrap();
+    output
+        .write_record(["address", "in_degree", "out_degree", "degree"])
+        .unwrap();
+
+    let in_blocks = from_block.is_some() || to_block.is_some();
+    for addr in v.iter() {
+        let id = subgraph::to_uuid(addr, v_type);
+        let d = if in_blocks {
+            degree_in_blocks(
+                datastore,
+                id,
+                from_block.unwrap_or(0),
+                to_block.unwrap_or(u64::MAX),
+            )
+        } else {
+            degree(datastore, id)
+        };
+
+        output
+            .write_record([
+                addr.as_str(),
+                d.in_degree.to_string().as_str(),
+                d.out_degree.to_string().as_str(),
+                d.degree.to_string().as_str(),
+            ])
+            .unwrap();
+    }
+    output.flush().unwrap();
+}
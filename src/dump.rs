@@ -1,27 +1,119 @@
+use std::io::{self, BufWriter, Write};
+
 use ethers::utils::hex;
 use rocksdb::{IteratorMode, Options, DB};
+use serde_json::{json, Value};
 use uuid::Uuid;
 
-const CF_NAMES: [&str; 8] = [
-    "vertices:v2",
-    "edge_ranges:v2",
-    "reversed_edge_ranges:v2",
-    "vertex_properties:v2",
-    "edge_properties:v2",
-    "vertex_property_values:v2",
-    "edge_property_values:v2",
-    "metadata:v2",
-];
+use crate::{
+    codec,
+    rocks::{self, CF_NAMES},
+    store,
+};
+
+/// the property value, the edge details are decoded into the transaction unless malformed
+fn property_value(name: &str, value: &[u8]) -> Value {
+    let value: Value = serde_json::from_slice(value).unwrap();
+    if name != "details" {
+        return value;
+    }
+    match codec::decode_details(&value) {
+        Ok(tx) => serde_json::to_value(tx).unwrap(),
+        Err(e) => {
+            log::warn!("the details are kept as stored: {}", e);
+            value
+        }
+    }
+}
+
+/// the address of the vertex, or its uuid when it has no vertex
+fn address(db: &DB, id: Uuid) -> String {
+    rocks::get_vertex(db, id).unwrap_or_else(|| id.to_string())
+}
+
+/// decode the row by the key layout of the column family, the ids are resolved to the addresses
+pub fn decode_row(db: &DB, cf_name: &str, key: &[u8], value: &[u8]) -> Value {
+    match cf_name {
+        "vertices:v2" => {
+            let (id, _) = rocks::read_uuid(key);
+            json!({ "id": id.to_string(), "address": rocks::decode_vertex_value(value) })
+        }
+        "edge_ranges:v2" => {
+            let (from, t, to) = rocks::decode_edge_range_key(key);
+            json!({ "from": address(db, from), "to": address(db, to), "tx": t })
+        }
+        "reversed_edge_ranges:v2" => {
+            let (to, t, from) = rocks::decode_edge_range_key(key);
+            json!({ "from": address(db, from), "to": address(db, to), "tx": t })
+        }
+        "vertex_properties:v2" => {
+            let (id, name) = rocks::decode_vertex_property_key(key);
+            json!({
+                "id": id.to_string(),
+                "address": address(db, id),
+                "name": name,
+                "value": property_value(name, value),
+            })
+        }
+        "edge_properties:v2" => {
+            let (from, t, to, name) = rocks::decode_edge_property_key(key);
+            json!({
+                "from": address(db, from),
+                "to": address(db, to),
+                "tx": t,
+                "name": name,
+                "value": property_value(name, value),
+            })
+        }
+        // the indexes only keep the hash of the value
+        "vertex_property_values:v2" => {
+            let (name, hash, id) = rocks::decode_vertex_property_value_key(key);
+            json!({
+                "name": name,
+                "hash": format!("{:016x}", hash),
+                "id": id.to_string(),
+                "address": address(db, id),
+            })
+        }
+        "edge_property_values:v2" => {
+            let (name, hash, from, t, to) = rocks::decode_edge_property_value_key(key);
+            json!({
+                "name": name,
+                "hash": format!("{:016x}", hash),
+                "from": address(db, from),
+                "to": address(db, to),
+                "tx": t,
+            })
+        }
+        _ => json!({
+            "key": String::from_utf8_lossy(key),
+            "value": serde_json::from_slice(value).unwrap_or_else(|_| Value::from(hex::encode(value))),
+        }),
+    }
+}
 
-pub fn json(path: String, opts: &Options) {
-    let db = DB::open_cf(opts, path, CF_NAMES).unwrap();
+/// print the column families as json lines, decoded or in hex
+/// the column families are checked by the cli against `CF_NAMES`
+pub fn json(path: String, opts: &Options, mode: store::OpenMode, cf: Vec<String>, raw: bool) {
+    let db = store::open_raw(path, opts, mode);
+    let mut out = BufWriter::new(io::stdout().lock());
     for cf_name in CF_NAMES {
+        if !cf.is_empty() && !cf.iter().any(|name| name == cf_name) {
+            continue;
+        }
+
         let iter = db.iterator_cf(db.cf_handle(cf_name).unwrap(), IteratorMode::Start);
         for row in iter {
             let (k, v) = row.unwrap();
-            let key = hex::encode(k);
-            let value = hex::encode(v);
-            println!("{cf_name}: {key:?} -> {value:?}");
+            let mut line = if raw {
+                json!({ "key": hex::encode(k), "value": hex::encode(v) })
+            } else {
+                decode_row(&db, cf_name, &k, &v)
+            };
+            line["cf"] = Value::from(cf_name);
+            serde_json::to_writer(&mut out, &line).unwrap();
+            out.write_all(b"\n").unwrap();
         }
     }
+    out.flush().unwrap();
 }
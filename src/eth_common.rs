@@ -18,6 +18,14 @@ pub struct TransactionInfo {
     #[serde(default, rename = "blockNumber")]
     pub block_number: Option<U64>,
 
+    /// Block timestamp in seconds. None when linked before the timestamps were stored.
+    #[serde(
+        default,
+        rename = "blockTimestamp",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub block_timestamp: Option<U64>,
+
     /// Transaction Index. None when pending.
     #[serde(default, rename = "transactionIndex")]
     pub transaction_index: Option<U64>,
@@ -0,0 +1,462 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use indradb::{BulkInsertItem, Edge, Identifier, Json, RocksdbDatastore, Vertex};
+use rocksdb::{IteratorMode, Options, DB};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{codec, rocks, store};
+
+pub const FORMAT: &str = "chaingraph-dump";
+/// bumped on any incompatible change of the records
+pub const VERSION: u64 = 1;
+
+const MANIFEST: &str = "manifest.json";
+
+/// a line of the dump, the property values are kept as stored, e.g. the encoded details
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Record {
+    Vertex {
+        id: Uuid,
+        t: String,
+    },
+    Edge {
+        from: Uuid,
+        t: String,
+        to: Uuid,
+    },
+    VertexProperty {
+        id: Uuid,
+        name: String,
+        value: serde_json::Value,
+    },
+    EdgeProperty {
+        from: Uuid,
+        t: String,
+        to: Uuid,
+        name: String,
+        value: serde_json::Value,
+    },
+}
+
+impl Record {
+    fn kind(&self) -> &'static str {
+        match self {
+            Record::Vertex { .. } => "vertex",
+            Record::Edge { .. } => "edge",
+            Record::VertexProperty { .. } => "vertex_property",
+            Record::EdgeProperty { .. } => "edge_property",
+        }
+    }
+
+    fn into_item(self) -> BulkInsertItem {
+        let id = |t: &str| Identifier::new(t).unwrap();
+        match self {
+            Record::Vertex { id: v, t } => BulkInsertItem::Vertex(Vertex::with_id(v, id(&t))),
+            Record::Edge { from, t, to } => BulkInsertItem::Edge(Edge::new(from, id(&t), to)),
+            Record::VertexProperty { id: v, name, value } => {
+                BulkInsertItem::VertexProperty(v, id(&name), Json::new(value))
+            }
+            Record::EdgeProperty {
+                from,
+                t,
+                to,
+                name,
+                value,
+            } => BulkInsertItem::EdgeProperty(
+                Edge::new(from, id(&t), to),
+                id(&name),
+                Json::new(value),
+            ),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Manifest {
+    pub format: String,
+    pub version: u64,
+    /// unix seconds
+    pub created: u64,
+    /// the gzipped json lines files, in order
+    pub chunks: Vec<String>,
+    /// the record count of each type
+    pub counts: BTreeMap<String, u64>,
+    /// the details format of the source, the dumps before it was kept are read as json
+    #[serde(default = "default_details_format")]
+    pub details_format: u64,
+}
+
+fn default_details_format() -> u64 {
+    codec::FORMAT_JSON
+}
+
+/// writes the records into the chunks of `chunk_size` lines, and the manifest at last
+pub struct Writer {
+    dir: PathBuf,
+    chunk_size: usize,
+    chunk: Option<GzEncoder<BufWriter<File>>>,
+    lines: usize,
+    manifest: Manifest,
+}
+
+impl Writer {
+    pub fn create<P: AsRef<Path>>(dir: P, chunk_size: usize) -> Result<Self, String> {
+        let dir = dir.as_ref().to_path_buf();
+        if dir.join(MANIFEST).exists() {
+            return Err(format!("{} already holds a dump", dir.display()));
+        }
+        fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+
+        Ok(Writer {
+            dir,
+            chunk_size: chunk_size.max(1),
+            chunk: None,
+            lines: 0,
+            manifest: Manifest {
+                format: FORMAT.to_owned(),
+                version: VERSION,
+                created: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs(),
+                details_format: codec::FORMAT_JSON,
+                ..Default::default()
+            },
+        })
+    }
+
+    pub fn write(&mut self, record: &Record) {
+        if self.chunk.is_none() || self.lines == self.chunk_size {
+            self.finish_chunk();
+            let name = format!("part-{:05}.jsonl.gz", self.manifest.chunks.len());
+            let file = BufWriter::new(File::create(self.dir.join(&name)).unwrap());
+            self.chunk = Some(GzEncoder::new(file, Compression::default()));
+            self.manifest.chunks.push(name);
+            self.lines = 0;
+        }
+
+        let chunk = self.chunk.as_mut().unwrap();
+        serde_json::to_writer(&mut *chunk, record).unwrap();
+        chunk.write_all(b"\n").unwrap();
+        self.lines += 1;
+        *self
+            .manifest
+            .counts
+            .entry(record.kind().to_owned())
+            .or_default() += 1;
+    }
+
+    fn finish_chunk(&mut self) {
+        if let Some(chunk) = self.chunk.take() {
+            chunk.finish().unwrap().flush().unwrap();
+        }
+    }
+
+    pub fn finish(mut self) -> Manifest {
+        self.finish_chunk();
+        let manifest = serde_json::to_string_pretty(&self.manifest).unwrap();
+        fs::write(self.dir.join(MANIFEST), manifest).unwrap();
+        self.manifest
+    }
+}
+
+/// the manifest and the records of the dump in `dir`, a malformed line is an error record
+pub fn read<P: AsRef<Path>>(
+    dir: P,
+) -> Result<(Manifest, impl Iterator<Item = Result<Record, String>>), String> {
+    let dir = dir.as_ref().to_path_buf();
+    let manifest = fs::read(dir.join(MANIFEST)).map_err(|e| format!("no manifest: {}", e))?;
+    let manifest: Manifest = serde_json::from_slice(&manifest).map_err(|e| e.to_string())?;
+    if manifest.format != FORMAT {
+        return Err(format!("{} is not a dump", dir.display()));
+    }
+    if manifest.version > VERSION {
+        return Err(format!(
+            "the dump version {} is newer than {}",
+            manifest.version, VERSION
+        ));
+    }
+
+    let records = manifest.chunks.clone().into_iter().flat_map(move |name| {
+        let lines: Box<dyn Iterator<Item = Result<Record, String>>> =
+            match File::open(dir.join(&name)) {
+                Err(e) => Box::new(std::iter::once(Err(format!("{}: {}", name, e)))),
+                Ok(file) => Box::new(BufReader::new(GzDecoder::new(file)).lines().map(
+                    move |line| {
+                        let line = line.map_err(|e| format!("{}: {}", name, e))?;
+                        serde_json::from_str::<Record>(&line)
+                            .map_err(|e| format!("{}: {}", name, e))
+                    },
+                )),
+            };
+        lines
+    });
+    Ok((manifest, records))
+}
+
+/// read the whole dump once, so a broken or incomplete one is refused before anything is written
+fn verify(dir: &str) -> Result<Manifest, String> {
+    let (manifest, records) = read(dir)?;
+    let mut counts: BTreeMap<String, u64> = BTreeMap::new();
+    for record in records {
+        *counts.entry(record?.kind().to_owned()).or_default() += 1;
+    }
+    if counts != manifest.counts {
+        return Err(format!(
+            "the dump is incomplete, {:?} rather than {:?}",
+            counts, manifest.counts
+        ));
+    }
+    Ok(manifest)
+}
+
+/// dump the vertices, the edges and all their properties
+pub fn export(
+    path: String,
+    opts: &Options,
+    mode: store::OpenMode,
+    dir: String,
+    chunk_size: usize,
+) -> Result<(), String> {
+    let mut writer = Writer::create(&dir, chunk_size)?;
+    let db = store::open_raw(path, opts, mode);
+    writer.manifest.details_format = codec::read_format(&db);
+
+    for cf_name in [
+        "vertices:v2",
+        "edge_ranges:v2",
+        "vertex_properties:v2",
+        "edge_properties:v2",
+    ] {
+        let iter = db.iterator_cf(db.cf_handle(cf_name).unwrap(), IteratorMode::Start);
+        for row in iter {
+            let (k, v) = row.unwrap();
+            let record = match cf_name {
+                "vertices:v2" => Record::Vertex {
+                    id: rocks::read_uuid(&k).0,
+                    t: rocks::decode_vertex_value(&v).to_owned(),
+                },
+                "edge_ranges:v2" => {
+                    let (from, t, to) = rocks::decode_edge_range_key(&k);
+                    Record::Edge {
+                        from,
+                        t: t.to_owned(),
+                        to,
+                    }
+                }
+                "vertex_properties:v2" => {
+                    let (id, name) = rocks::decode_vertex_property_key(&k);
+                    Record::VertexProperty {
+                        id,
+                        name: name.to_owned(),
+                        value: serde_json::from_slice(&v).unwrap(),
+                    }
+                }
+                _ => {
+                    let (from, t, to, name) = rocks::decode_edge_property_key(&k);
+                    Record::EdgeProperty {
+                        from,
+                        t: t.to_owned(),
+                        to,
+                        name: name.to_owned(),
+                        value: serde_json::from_slice(&v).unwrap(),
+                    }
+                }
+            };
+            writer.write(&record);
+        }
+        log::warn!("{} dumped", cf_name);
+    }
+
+    let manifest = writer.finish();
+    log::warn!(
+        "{} chunks in {}: {:?}",
+        manifest.chunks.len(),
+        dir,
+        manifest.counts
+    );
+    Ok(())
+}
+
+/// load a dump into the datastore, the property indexes have to be created again
+pub fn import(
+    path: String,
+    opts: &mut Options,
+    dir: String,
+    batch_size: usize,
+) -> Result<(), String> {
+    let manifest = verify(&dir)?;
+    log::warn!(
+        "{} chunks of version {}: {:?}",
+        manifest.chunks.len(),
+        manifest.version,
+        manifest.counts
+    );
+
+    // the details of both would be readable, but the new edges would take the dump's format
+    if Path::new(&path).join("CURRENT").exists() {
+        let stored = codec::stored_format(&rocks::open_read_only(&path, opts));
+        if let Some(format) = stored.filter(|&format| format != manifest.details_format) {
+            return Err(format!(
+                "{} keeps the details in format {}, the dump in {}, convert either first",
+                path, format, manifest.details_format
+            ));
+        }
+    }
+
+    let datastore = RocksdbDatastore::new_db_with_options(&path, opts).unwrap();
+    let (_, records) = read(&dir)?;
+    let mut items = Vec::with_capacity(batch_size);
+    let mut count = 0;
+    for record in records {
+        items.push(record?.into_item());
+        if items.len() >= batch_size {
+            count += items.len();
+            datastore.bulk_insert(std::mem::take(&mut items)).unwrap();
+            log::debug!("{} records imported", count);
+        }
+    }
+    datastore.bulk_insert(items).unwrap();
+    datastore.sync().unwrap();
+    drop(datastore);
+
+    let db = DB::open_cf(opts, &path, rocks::CF_NAMES).unwrap();
+    codec::write_format(&db, manifest.details_format);
+    log::warn!("imported {:?}", manifest.counts);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("chaingraph-test-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_writer_reader() {
+        let dir = temp_dir("dump-chunks");
+        let (a, b) = (Uuid::from_bytes([1; 16]), Uuid::from_bytes([2; 16]));
+        let records = vec![
+            Record::Vertex {
+                id: a,
+                t: "0xa".to_owned(),
+            },
+            Record::Vertex {
+                id: b,
+                t: "0xb".to_owned(),
+            },
+            Record::Edge {
+                from: a,
+                t: "ab".to_owned(),
+                to: b,
+            },
+            Record::EdgeProperty {
+                from: a,
+                t: "ab".to_owned(),
+                to: b,
+                name: "details".to_owned(),
+                value: serde_json::json!({"value": "0x1", "is_create": false}),
+            },
+            Record::VertexProperty {
+                id: a,
+                name: "cluster".to_owned(),
+                value: serde_json::json!("0xa"),
+            },
+        ];
+
+        let mut writer = Writer::create(&dir, 2).unwrap();
+        for r in &records {
+            writer.write(r);
+        }
+        let manifest = writer.finish();
+        assert_eq!(manifest.chunks.len(), 3);
+        assert_eq!(manifest.counts["vertex"], 2);
+
+        let (read_manifest, read_records) = read(&dir).unwrap();
+        assert_eq!(read_manifest, manifest);
+        assert_eq!(read_records.collect::<Result<Vec<_>, _>>(), Ok(records));
+        assert!(Writer::create(&dir, 2).is_err());
+        assert!(verify(dir.to_str().unwrap()).is_ok());
+
+        // a lost chunk is refused before the import writes anything
+        fs::remove_file(dir.join(&manifest.chunks[2])).unwrap();
+        assert!(verify(dir.to_str().unwrap()).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_export_import_lossless() {
+        let root = temp_dir("dump-roundtrip");
+        let src = root.join("src").to_string_lossy().into_owned();
+        let dst = root.join("dst").to_string_lossy().into_owned();
+        let dump = root.join("dump").to_string_lossy().into_owned();
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+
+        {
+            let db = RocksdbDatastore::new_db_with_options(&src, &mut opts).unwrap();
+            let (a, b) = (Uuid::from_bytes([1; 16]), Uuid::from_bytes([2; 16]));
+            let edge = Edge::new(a, Identifier::new("ab").unwrap(), b);
+            let name = |name: &str| Identifier::new(name).unwrap();
+            db.bulk_insert(vec![
+                BulkInsertItem::Vertex(Vertex::with_id(a, name("0xa"))),
+                BulkInsertItem::Vertex(Vertex::with_id(b, name("0xb"))),
+                BulkInsertItem::Edge(edge.clone()),
+                BulkInsertItem::Edge(Edge::new(b, name("ba"), a)),
+                BulkInsertItem::EdgeProperty(
+                    edge,
+                    name("details"),
+                    Json::new(serde_json::json!("00ff")),
+                ),
+                BulkInsertItem::VertexProperty(
+                    a,
+                    name("cluster"),
+                    Json::new(serde_json::json!({"id": "0xa", "size": 2})),
+                ),
+            ])
+            .unwrap();
+        }
+
+        export(
+            src.clone(),
+            &opts,
+            store::OpenMode::ReadOnly,
+            dump.clone(),
+            2,
+        )
+        .unwrap();
+        import(dst.clone(), &mut opts, dump.clone(), 2).unwrap();
+
+        let src_db = rocks::open_read_only(&src, &opts);
+        let dst_db = rocks::open_read_only(&dst, &opts);
+        for cf_name in [
+            "vertices:v2",
+            "edge_ranges:v2",
+            "reversed_edge_ranges:v2",
+            "vertex_properties:v2",
+            "edge_properties:v2",
+        ] {
+            let rows = |db: &rocksdb::DB| {
+                db.iterator_cf(db.cf_handle(cf_name).unwrap(), IteratorMode::Start)
+                    .map(|row| row.unwrap())
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(rows(&src_db), rows(&dst_db), "{} differs", cf_name);
+        }
+
+        drop((src_db, dst_db));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -4,223 +4,836 @@ use std::{
     sync::{Arc, Mutex},
 };
 
-use crate::{utils, eth_common::TransactionInfo};
-use bigdecimal::{BigDecimal, ToPrimitive, Zero};
-use ethers::{prelude::*, providers::Provider, utils::WEI_IN_ETHER};
+use crate::{
+    cluster, codec,
+    degree::{self, Degree},
+    eth_common::TransactionInfo,
+    index,
+    store::{self, Store},
+    subgraph::{self, Direction, Stopper},
+    utils,
+};
+use bigdecimal::{num_bigint::BigInt, BigDecimal};
+use ethers::{prelude::*, utils::get_contract_address};
+use hashbrown::{HashMap, HashSet};
 use indradb::{
-    Database, EdgeProperty, EdgeWithPropertyPresenceQuery, Identifier, Json, PipePropertyQuery,
-    QueryExt, QueryOutputValue, RocksdbDatastore, SpecificEdgeQuery, SpecificVertexQuery, Vertex,
+    Database, Datastore, Edge, Identifier, QueryExt, QueryOutputValue, SpecificEdgeQuery,
+    SpecificVertexQuery, Vertex,
 };
-use rocksdb::{Options};
-use serde::{Deserialize, Serialize};
+use rocksdb::Options;
+use tokio::sync::Semaphore;
 use uuid::Uuid;
 
-#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
-struct AddressFeature {
-    addr: H160,
-    // bal: f64,
-    sum_val_in: f64,
-    sum_val_out: f64,
-
-    avg_val_in: f64,
-    avg_val_out: f64,
-
-    count: f64,
-    count_in: f64,
-    count_out: f64,
-
-    freq: f64,
-    freq_in: f64,
-    freq_out: f64,
-
-    gini_val: f64,
-    gini_val_in: f64,
-    gini_val_out: f64,
-
-    avg_gas: f64,
-    avg_gas_in: f64,
-    avg_gas_out: f64,
-
-    avg_gasprice: f64,
-    avg_gasprice_in: f64,
-    avg_gasprice_out: f64,
-
-    in_out_rate: f64,
-}
-
-impl AddressFeature {
-    fn new(
-        addr: H160,
-        // bal: f64,
-        val_in_list: Vec<f64>,
-        val_out_list: Vec<f64>,
-        height_in_list: Vec<u64>,
-        height_out_list: Vec<u64>,
-        gas_in_list: Vec<f64>,
-        gas_out_list: Vec<f64>,
-        gasprice_in_list: Vec<f64>,
-        gasprice_out_list: Vec<f64>,
+/// a feature value, the exact decimals are only turned into float at output time
+#[derive(Debug, Clone, PartialEq)]
+pub enum FeatureValue {
+    Float(f64),
+    Decimal(BigDecimal),
+}
+
+impl FeatureValue {
+    fn from_avg(avg: Option<BigDecimal>, default: f64) -> Self {
+        match avg {
+            None => FeatureValue::Float(default),
+            Some(avg) => FeatureValue::Decimal(avg),
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            FeatureValue::Float(f) => *f,
+            FeatureValue::Decimal(d) => utils::decimal_to_f64(d),
+        }
+    }
+
+    pub fn to_output(&self, exact: bool) -> String {
+        match self {
+            FeatureValue::Decimal(d) if exact => d.normalized().to_string(),
+            _ => self.to_f64().to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Counterparty {
+    /// created by its earliest inbound tx, see `is_contract`
+    pub is_contract: bool,
+    pub degree: Degree,
+}
+
+/// everything a feature function can see about the seed address
+#[derive(Debug, Clone, Default)]
+pub struct AddressContext {
+    pub addr: H160,
+    pub txs_in: Vec<TransactionInfo>,
+    pub txs_out: Vec<TransactionInfo>,
+    /// only filled when a selected feature needs it
+    pub counterparties: HashMap<H160, Counterparty>,
+}
+
+impl AddressContext {
+    fn txs(&self) -> impl Iterator<Item = &TransactionInfo> {
+        self.txs_in.iter().chain(self.txs_out.iter())
+    }
+
+    fn count(&self) -> usize {
+        self.txs_in.len() + self.txs_out.len()
+    }
+
+    /// the sender of the inbound txs and the receiver of the outbound txs,
+    /// the receiver of a create tx is the created contract
+    pub fn counterparties_in(&self) -> impl Iterator<Item = H160> + '_ {
+        self.txs_in.iter().map(|tx| tx.from)
+    }
+
+    pub fn counterparties_out(&self) -> impl Iterator<Item = H160> + '_ {
+        self.txs_out.iter().map(|tx| {
+            tx.to
+                .unwrap_or_else(|| get_contract_address(tx.from, tx.nonce))
+        })
+    }
+
+    pub fn unique_counterparties(&self) -> HashSet<H160> {
+        self.counterparties_in()
+            .chain(self.counterparties_out())
+            .collect()
+    }
+}
+
+pub struct Feature {
+    pub family: &'static str,
+    pub name: &'static str,
+    pub func: fn(&AddressContext) -> FeatureValue,
+    /// whether `AddressContext::counterparties` is required
+    pub needs_counterparties: bool,
+}
+
+impl Feature {
+    const fn seed(
+        family: &'static str,
+        name: &'static str,
+        func: fn(&AddressContext) -> FeatureValue,
     ) -> Self {
-        let count_in = val_in_list.len() as f64;
-        let count_out = val_out_list.len() as f64;
-        let count = count_in + count_out;
+        Feature {
+            family,
+            name,
+            func,
+            needs_counterparties: false,
+        }
+    }
 
-        let sum_val_in = val_in_list.iter().sum();
-        let sum_val_out = val_out_list.iter().sum();
+    const fn counterparty(
+        family: &'static str,
+        name: &'static str,
+        func: fn(&AddressContext) -> FeatureValue,
+    ) -> Self {
+        Feature {
+            family,
+            name,
+            func,
+            needs_counterparties: true,
+        }
+    }
 
-        let avg_val_in = if count_in.is_zero() {
-            0.
-        } else {
-            sum_val_in / count_in
-        };
-        let avg_val_out = if count_out.is_zero() {
-            0.
-        } else {
-            sum_val_out / count_out
-        };
+    /// `*`, `family.*` or `family.name`
+    pub fn matches(&self, pattern: &str) -> bool {
+        match pattern.split_once('.') {
+            None => pattern == "*" || pattern == self.family,
+            Some((family, name)) => family == self.family && (name == "*" || name == self.name),
+        }
+    }
+}
 
-        let max_height_in = height_in_list.iter().max();
-        let min_height_in = height_in_list.iter().min();
-        let max_height_out = height_out_list.iter().max();
-        let min_height_out = height_out_list.iter().min();
-        let max_height = u64::max(*max_height_in.unwrap_or(&0), *max_height_out.unwrap_or(&0));
-        let min_height = u64::min(
-            *min_height_in.unwrap_or(&16_800_000),
-            *min_height_in.unwrap_or(&16_800_000),
-        );
+/// all features, in the output order
+pub static FEATURES: &[Feature] = &[
+    Feature::seed("value", "sum_val_in", sum_val_in),
+    Feature::seed("value", "sum_val_out", sum_val_out),
+    Feature::seed("value", "avg_val_in", avg_val_in),
+    Feature::seed("value", "avg_val_out", avg_val_out),
+    Feature::seed("timing", "count", count),
+    Feature::seed("timing", "count_in", count_in),
+    Feature::seed("timing", "count_out", count_out),
+    Feature::seed("timing", "freq", freq),
+    Feature::seed("timing", "freq_in", freq_in),
+    Feature::seed("timing", "freq_out", freq_out),
+    Feature::seed("value", "gini_val", gini_val),
+    Feature::seed("value", "gini_val_in", gini_val_in),
+    Feature::seed("value", "gini_val_out", gini_val_out),
+    Feature::seed("gas", "avg_gas", avg_gas),
+    Feature::seed("gas", "avg_gas_in", avg_gas_in),
+    Feature::seed("gas", "avg_gas_out", avg_gas_out),
+    Feature::seed("gas", "avg_gasprice", avg_gasprice),
+    Feature::seed("gas", "avg_gasprice_in", avg_gasprice_in),
+    Feature::seed("gas", "avg_gasprice_out", avg_gasprice_out),
+    Feature::seed("timing", "in_out_rate", in_out_rate),
+    Feature::seed("timing", "first_block", first_block),
+    Feature::seed("timing", "last_block", last_block),
+    Feature::seed("timing", "lifetime", lifetime),
+    Feature::seed("timing", "active_blocks", active_blocks),
+    Feature::seed("timing", "gap_mean", gap_mean),
+    Feature::seed("timing", "gap_std", gap_std),
+    Feature::seed("timing", "gap_min", gap_min),
+    Feature::seed("timing", "gap_max", gap_max),
+    Feature::seed("timing", "burstiness", burstiness),
+    Feature::seed("timing", "active_days", active_days),
+    Feature::seed("timing", "hour_entropy", hour_entropy),
+    Feature::seed("counterparty", "unique_in", unique_in),
+    Feature::seed("counterparty", "unique_out", unique_out),
+    Feature::seed("counterparty", "repeat_rate", repeat_rate),
+    Feature::seed("counterparty", "top1_share_out", top1_share_out),
+    Feature::seed("counterparty", "top3_share_out", top3_share_out),
+    Feature::seed("counterparty", "top3_share_in", top3_share_in),
+    Feature::counterparty("counterparty", "contract_rate", contract_rate),
+    Feature::counterparty("counterparty", "avg_degree", avg_degree),
+    Feature::counterparty("counterparty", "max_degree", max_degree),
+    Feature::counterparty("counterparty", "std_degree", std_degree),
+    Feature::seed("contract", "create_count", create_count),
+    Feature::seed("contract", "call_rate", call_rate),
+];
 
-        let interval_in = match max_height_in {
-            None => 0u64,
-            Some(&max) => max - min_height_in.unwrap_or(&0),
-        };
-        let interval_out = match max_height_in {
-            None => 0u64,
-            Some(&max) => max - min_height_out.unwrap_or(&0),
-        };
-        let interval = if max_height <= min_height {
-            0
-        } else {
-            max_height - min_height
-        };
+/// the first pattern which matches no feature
+pub fn unknown_feature(patterns: &[String]) -> Option<&String> {
+    patterns
+        .iter()
+        .find(|p| !FEATURES.iter().any(|f| f.matches(p)))
+}
 
-        let freq = if interval == 0 {
-            f64::NAN
-        } else {
-            count / (interval as f64)
-        };
-        let freq_in = if interval_in == 0 {
-            f64::NAN
-        } else {
-            count_in / (interval_in as f64)
-        };
-        let freq_out = if interval_out == 0 {
-            f64::NAN
-        } else {
-            count_out / (interval_out as f64)
-        };
+/// select the features by the patterns, all features when no pattern is given
+pub fn select_features(patterns: &[String]) -> Result<Vec<&'static Feature>, String> {
+    if patterns.is_empty() {
+        return Ok(FEATURES.iter().collect());
+    }
 
-        let val_list = [val_in_list.clone(), val_out_list.clone()].concat();
+    if let Some(pattern) = unknown_feature(patterns) {
+        return Err(format!("unknown feature: {}", pattern));
+    }
 
-        let gini_val = utils::gini(&val_list);
-        let gini_val_in = utils::gini(&val_in_list);
-        let gini_val_out = utils::gini(&val_out_list);
+    Ok(FEATURES
+        .iter()
+        .filter(|f| patterns.iter().any(|p| f.matches(p)))
+        .collect())
+}
 
-        let sum_gas_in: f64 = gas_in_list.iter().sum();
-        let sum_gas_out: f64 = gas_out_list.iter().sum();
+// value
 
-        let avg_gas = (sum_gas_in + sum_gas_out) / (count_in + count_out);
-        let avg_gas_in = if count_in.is_zero() {
-            f64::NAN
-        } else {
-            sum_gas_in / count_in
-        };
-        let avg_gas_out = if count_out.is_zero() {
-            f64::NAN
-        } else {
-            sum_gas_out / count_out
-        };
+/// in ether
+fn sum_val(txs: &[TransactionInfo]) -> BigDecimal {
+    let wei: Vec<U256> = txs.iter().map(|tx| tx.value).collect();
+    BigDecimal::new(utils::sum_wei(&wei), utils::ETHER_DECIMALS)
+}
 
-        let sum_gasprice_in: f64 = gasprice_in_list.iter().sum();
-        let sum_gasprice_out: f64 = gasprice_out_list.iter().sum();
+fn avg_val(txs: &[TransactionInfo]) -> FeatureValue {
+    if txs.is_empty() {
+        return FeatureValue::Float(0.);
+    }
+    FeatureValue::Decimal(&sum_val(txs) / &BigDecimal::from(txs.len() as u64))
+}
 
-        let avg_gasprice = (sum_gasprice_in + sum_gasprice_out) / (count_in + count_out);
-        let avg_gasprice_in = if count_in.is_zero() {
-            f64::NAN
-        } else {
-            sum_gasprice_in / count_in
-        };
-        let avg_gasprice_out = if count_out.is_zero() {
-            f64::NAN
-        } else {
-            sum_gasprice_out / count_out
-        };
+/// gini is a ratio, so the float is precise enough
+fn gini<'a>(txs: impl Iterator<Item = &'a TransactionInfo>) -> FeatureValue {
+    let val_list: Vec<f64> = txs
+        .map(|tx| utils::decimal_to_f64(&utils::wei_to_ether(tx.value)))
+        .collect();
+    FeatureValue::Float(utils::gini(&val_list))
+}
 
-        let in_out_rate = if count_out.is_zero() {
-            0.
-        } else {
-            count_in / count_out
-        };
+fn sum_val_in(ctx: &AddressContext) -> FeatureValue {
+    FeatureValue::Decimal(sum_val(&ctx.txs_in))
+}
 
-        AddressFeature {
-            addr,
-            // bal,
-            sum_val_in,
-            sum_val_out,
+fn sum_val_out(ctx: &AddressContext) -> FeatureValue {
+    FeatureValue::Decimal(sum_val(&ctx.txs_out))
+}
 
-            avg_val_in,
-            avg_val_out,
+fn avg_val_in(ctx: &AddressContext) -> FeatureValue {
+    avg_val(&ctx.txs_in)
+}
 
-            count,
-            count_in,
-            count_out,
+fn avg_val_out(ctx: &AddressContext) -> FeatureValue {
+    avg_val(&ctx.txs_out)
+}
 
-            freq,
-            freq_in,
-            freq_out,
+fn gini_val(ctx: &AddressContext) -> FeatureValue {
+    gini(ctx.txs())
+}
 
-            gini_val,
-            gini_val_in,
-            gini_val_out,
+fn gini_val_in(ctx: &AddressContext) -> FeatureValue {
+    gini(ctx.txs_in.iter())
+}
 
-            avg_gas,
-            avg_gas_in,
-            avg_gas_out,
+fn gini_val_out(ctx: &AddressContext) -> FeatureValue {
+    gini(ctx.txs_out.iter())
+}
+
+// timing
+
+/// count / block interval, the interval is at least a block so a single block isn't NaN
+fn freq_of<'a>(txs: impl Iterator<Item = &'a TransactionInfo>) -> FeatureValue {
+    let heights: Vec<u64> = txs
+        .filter_map(|tx| tx.block_number)
+        .map(|n| n.as_u64())
+        .collect();
+    let interval = match (heights.iter().min(), heights.iter().max()) {
+        (Some(min), Some(max)) => max - min,
+        _ => 0,
+    };
+
+    FeatureValue::Float(heights.len() as f64 / interval.max(1) as f64)
+}
+
+fn count(ctx: &AddressContext) -> FeatureValue {
+    FeatureValue::Float(ctx.count() as f64)
+}
+
+fn count_in(ctx: &AddressContext) -> FeatureValue {
+    FeatureValue::Float(ctx.txs_in.len() as f64)
+}
+
+fn count_out(ctx: &AddressContext) -> FeatureValue {
+    FeatureValue::Float(ctx.txs_out.len() as f64)
+}
+
+fn freq(ctx: &AddressContext) -> FeatureValue {
+    freq_of(ctx.txs())
+}
+
+fn freq_in(ctx: &AddressContext) -> FeatureValue {
+    freq_of(ctx.txs_in.iter())
+}
+
+fn freq_out(ctx: &AddressContext) -> FeatureValue {
+    freq_of(ctx.txs_out.iter())
+}
+
+fn in_out_rate(ctx: &AddressContext) -> FeatureValue {
+    if ctx.txs_out.is_empty() {
+        FeatureValue::Float(0.)
+    } else {
+        FeatureValue::Float(ctx.txs_in.len() as f64 / ctx.txs_out.len() as f64)
+    }
+}
+
+/// all block numbers, sorted
+fn heights(ctx: &AddressContext) -> Vec<u64> {
+    let mut heights: Vec<u64> = ctx
+        .txs()
+        .map(|tx| tx.block_number.unwrap().as_u64())
+        .collect();
+    heights.sort();
+    heights
+}
+
+/// the block gaps between the consecutive txs
+fn gaps(ctx: &AddressContext) -> Vec<f64> {
+    heights(ctx)
+        .windows(2)
+        .map(|w| (w[1] - w[0]) as f64)
+        .collect()
+}
+
+/// all block timestamps, None when any tx has no timestamp stored
+fn timestamps(ctx: &AddressContext) -> Option<Vec<u64>> {
+    ctx.txs()
+        .map(|tx| tx.block_timestamp.map(|t| t.as_u64()))
+        .collect()
+}
+
+fn first_block(ctx: &AddressContext) -> FeatureValue {
+    let first = heights(ctx).first().map_or(f64::NAN, |&h| h as f64);
+    FeatureValue::Float(first)
+}
+
+fn last_block(ctx: &AddressContext) -> FeatureValue {
+    let last = heights(ctx).last().map_or(f64::NAN, |&h| h as f64);
+    FeatureValue::Float(last)
+}
+
+/// in blocks
+fn lifetime(ctx: &AddressContext) -> FeatureValue {
+    let heights = heights(ctx);
+    match (heights.first(), heights.last()) {
+        (Some(first), Some(last)) => FeatureValue::Float((last - first) as f64),
+        _ => FeatureValue::Float(f64::NAN),
+    }
+}
+
+fn active_blocks(ctx: &AddressContext) -> FeatureValue {
+    let mut heights = heights(ctx);
+    heights.dedup();
+    FeatureValue::Float(heights.len() as f64)
+}
+
+fn gap_mean(ctx: &AddressContext) -> FeatureValue {
+    FeatureValue::Float(utils::mean(&gaps(ctx)))
+}
+
+fn gap_std(ctx: &AddressContext) -> FeatureValue {
+    FeatureValue::Float(utils::std(&gaps(ctx)))
+}
+
+fn gap_min(ctx: &AddressContext) -> FeatureValue {
+    FeatureValue::Float(gaps(ctx).into_iter().fold(f64::NAN, f64::min))
+}
+
+fn gap_max(ctx: &AddressContext) -> FeatureValue {
+    FeatureValue::Float(gaps(ctx).into_iter().fold(f64::NAN, f64::max))
+}
 
-            avg_gasprice,
-            avg_gasprice_in,
-            avg_gasprice_out,
+/// (std - mean) / (std + mean) of the gaps, -1 for periodic and 1 for bursty
+fn burstiness(ctx: &AddressContext) -> FeatureValue {
+    let gaps = gaps(ctx);
+    let (mean, std) = (utils::mean(&gaps), utils::std(&gaps));
+    if mean + std == 0. {
+        return FeatureValue::Float(f64::NAN);
+    }
+    FeatureValue::Float((std - mean) / (std + mean))
+}
 
-            in_out_rate,
+/// the count of the distinct UTC days
+fn active_days(ctx: &AddressContext) -> FeatureValue {
+    match timestamps(ctx) {
+        None => FeatureValue::Float(f64::NAN),
+        Some(timestamps) => {
+            let days: HashSet<u64> = timestamps.iter().map(|t| t / 86_400).collect();
+            FeatureValue::Float(days.len() as f64)
         }
     }
 }
 
+/// the shannon entropy (in bits) of the UTC hour-of-day distribution
+fn hour_entropy(ctx: &AddressContext) -> FeatureValue {
+    let timestamps = match timestamps(ctx) {
+        Some(timestamps) if !timestamps.is_empty() => timestamps,
+        _ => return FeatureValue::Float(f64::NAN),
+    };
+
+    let mut hours = [0usize; 24];
+    for t in &timestamps {
+        hours[(t % 86_400 / 3_600) as usize] += 1;
+    }
+
+    let n = timestamps.len() as f64;
+    let entropy = hours
+        .iter()
+        .filter(|&&c| c > 0)
+        .map(|&c| {
+            let p = c as f64 / n;
+            -p * p.log2()
+        })
+        .sum();
+    FeatureValue::Float(entropy)
+}
+
+// gas
+
+/// in gas unit
+fn avg_gas_of<'a>(txs: impl Iterator<Item = &'a TransactionInfo>) -> FeatureValue {
+    let gas_list: Vec<U256> = txs.map(|tx| tx.gas).collect();
+    FeatureValue::from_avg(utils::avg_wei(&gas_list), f64::NAN)
+}
+
+/// in wei
+fn avg_gasprice_of<'a>(txs: impl Iterator<Item = &'a TransactionInfo>) -> FeatureValue {
+    let gasprice_list: Vec<U256> = txs
+        .map(|tx| tx.gas_price.unwrap_or(U256::from(0)))
+        .collect();
+    FeatureValue::from_avg(utils::avg_wei(&gasprice_list), f64::NAN)
+}
+
+fn avg_gas(ctx: &AddressContext) -> FeatureValue {
+    avg_gas_of(ctx.txs())
+}
+
+fn avg_gas_in(ctx: &AddressContext) -> FeatureValue {
+    avg_gas_of(ctx.txs_in.iter())
+}
+
+fn avg_gas_out(ctx: &AddressContext) -> FeatureValue {
+    avg_gas_of(ctx.txs_out.iter())
+}
+
+fn avg_gasprice(ctx: &AddressContext) -> FeatureValue {
+    avg_gasprice_of(ctx.txs())
+}
+
+fn avg_gasprice_in(ctx: &AddressContext) -> FeatureValue {
+    avg_gasprice_of(ctx.txs_in.iter())
+}
+
+fn avg_gasprice_out(ctx: &AddressContext) -> FeatureValue {
+    avg_gasprice_of(ctx.txs_out.iter())
+}
+
+// counterparty
+
+fn unique_in(ctx: &AddressContext) -> FeatureValue {
+    let senders: HashSet<H160> = ctx.counterparties_in().collect();
+    FeatureValue::Float(senders.len() as f64)
+}
+
+fn unique_out(ctx: &AddressContext) -> FeatureValue {
+    let receivers: HashSet<H160> = ctx.counterparties_out().collect();
+    FeatureValue::Float(receivers.len() as f64)
+}
+
+/// the rate of the counterparties interacted more than once
+fn repeat_rate(ctx: &AddressContext) -> FeatureValue {
+    let mut counts: HashMap<H160, usize> = HashMap::new();
+    for c in ctx.counterparties_in().chain(ctx.counterparties_out()) {
+        *counts.entry(c).or_default() += 1;
+    }
+    if counts.is_empty() {
+        return FeatureValue::Float(f64::NAN);
+    }
+    let repeated = counts.values().filter(|&&n| n > 1).count();
+    FeatureValue::Float(repeated as f64 / counts.len() as f64)
+}
+
+/// the share of the volume going to the top k counterparties
+fn top_k_share(
+    counterparties: impl Iterator<Item = H160>,
+    txs: &[TransactionInfo],
+    k: usize,
+) -> FeatureValue {
+    let mut volumes: HashMap<H160, BigInt> = HashMap::new();
+    for (c, tx) in counterparties.zip(txs) {
+        *volumes.entry(c).or_default() += utils::u256_to_bigint(tx.value);
+    }
+
+    let total: BigInt = volumes.values().sum();
+    if total == BigInt::default() {
+        return FeatureValue::Float(f64::NAN);
+    }
+
+    let mut volumes: Vec<BigInt> = volumes.into_values().collect();
+    volumes.sort_by(|a, b| b.cmp(a));
+    let top: BigInt = volumes.into_iter().take(k).sum();
+
+    FeatureValue::Decimal(BigDecimal::from(top) / BigDecimal::from(total))
+}
+
+fn top1_share_out(ctx: &AddressContext) -> FeatureValue {
+    top_k_share(ctx.counterparties_out(), &ctx.txs_out, 1)
+}
+
+fn top3_share_out(ctx: &AddressContext) -> FeatureValue {
+    top_k_share(ctx.counterparties_out(), &ctx.txs_out, 3)
+}
+
+fn top3_share_in(ctx: &AddressContext) -> FeatureValue {
+    top_k_share(ctx.counterparties_in(), &ctx.txs_in, 3)
+}
+
+fn contract_rate(ctx: &AddressContext) -> FeatureValue {
+    let unique = ctx.unique_counterparties();
+    if unique.is_empty() {
+        return FeatureValue::Float(f64::NAN);
+    }
+    let contracts = unique
+        .iter()
+        .filter(|c| ctx.counterparties.get(*c).map_or(false, |c| c.is_contract))
+        .count();
+    FeatureValue::Float(contracts as f64 / unique.len() as f64)
+}
+
+fn counterparty_degrees(ctx: &AddressContext) -> Vec<f64> {
+    ctx.unique_counterparties()
+        .iter()
+        .filter_map(|c| ctx.counterparties.get(c))
+        .map(|c| c.degree.degree as f64)
+        .collect()
+}
+
+fn avg_degree(ctx: &AddressContext) -> FeatureValue {
+    let degrees = counterparty_degrees(ctx);
+    FeatureValue::Float(utils::mean(&degrees))
+}
+
+fn max_degree(ctx: &AddressContext) -> FeatureValue {
+    let degrees = counterparty_degrees(ctx);
+    FeatureValue::Float(degrees.into_iter().fold(f64::NAN, f64::max))
+}
+
+fn std_degree(ctx: &AddressContext) -> FeatureValue {
+    let degrees = counterparty_degrees(ctx);
+    FeatureValue::Float(utils::std(&degrees))
+}
+
+// contract
+
+fn create_count(ctx: &AddressContext) -> FeatureValue {
+    FeatureValue::Float(ctx.txs_out.iter().filter(|tx| tx.is_create).count() as f64)
+}
+
+/// the rate of the outbound txs carrying call data
+fn call_rate(ctx: &AddressContext) -> FeatureValue {
+    if ctx.txs_out.is_empty() {
+        return FeatureValue::Float(f64::NAN);
+    }
+    let calls = ctx.txs_out.iter().filter(|tx| !tx.input.is_empty()).count();
+    FeatureValue::Float(calls as f64 / ctx.txs_out.len() as f64)
+}
+
+// neighbourhood
+
+/// (mean, max, sum), NaN on the empty list
+fn aggregate(values: &[f64]) -> [f64; 3] {
+    let max = values.iter().copied().fold(f64::NAN, f64::max);
+    let sum = if values.is_empty() {
+        f64::NAN
+    } else {
+        values.iter().sum()
+    };
+    [utils::mean(values), max, sum]
+}
+
+/// the density of the undirected ego network and the clustering coefficient of the seed
+fn ego_stats(seed: Uuid, pairs: &[(Uuid, Uuid)]) -> (f64, f64) {
+    let undirected = |a: Uuid, b: Uuid| if a < b { (a, b) } else { (b, a) };
+
+    let neighbours: HashSet<Uuid> = pairs
+        .iter()
+        .filter(|(a, b)| a != b && (*a == seed || *b == seed))
+        .map(|&(a, b)| if a == seed { b } else { a })
+        .collect();
+    let links: HashSet<(Uuid, Uuid)> = pairs
+        .iter()
+        .filter(|(a, b)| a != b)
+        .filter(|(a, b)| {
+            (*a == seed || neighbours.contains(a)) && (*b == seed || neighbours.contains(b))
+        })
+        .map(|&(a, b)| undirected(a, b))
+        .collect();
+
+    let k = (neighbours.len() + 1) as f64;
+    let density = if k < 2. {
+        f64::NAN
+    } else {
+        2. * links.len() as f64 / (k * (k - 1.))
+    };
+
+    let n = neighbours.len() as f64;
+    let between_neighbours = links
+        .iter()
+        .filter(|(a, b)| *a != seed && *b != seed)
+        .count() as f64;
+    let clustering = if n < 2. {
+        f64::NAN
+    } else {
+        2. * between_neighbours / (n * (n - 1.))
+    };
+
+    (density, clustering)
+}
+
 ////////////////////////////////////////////////////////////////
 
-#[derive(Clone)]
-pub struct FeatureExtracter {
-    db: Arc<Database<RocksdbDatastore>>,
-    wei_in_eth: BigDecimal,
-    f_output: Arc<Mutex<csv::Writer<File>>>,
+/// the max edge count of a single property query
+const BATCH_SIZE: usize = 10_000;
+
+/// fetch the `details` of the edges with batched pipe queries rather than one query per edge
+pub fn fetch_edge_details<D: Datastore>(
+    db: &Database<D>,
+    edges: Vec<Edge>,
+) -> Vec<(Edge, TransactionInfo)> {
+    let mut txs = Vec::with_capacity(edges.len());
+    for chunk in edges.chunks(BATCH_SIZE) {
+        let q = SpecificEdgeQuery::new(chunk.to_vec())
+            .properties()
+            .unwrap()
+            .name(Identifier::new("details").unwrap());
+        let properties = indradb::util::extract_edge_properties(db.get(q).unwrap()).unwrap();
+        for e_props in properties {
+            // the edges loaded from an edge list have no details
+            let tx = e_props
+                .props
+                .first()
+                .and_then(|prop| codec::decode_or_skip(prop.value.0.as_ref()));
+            if let Some(tx) = tx {
+                txs.push((e_props.edge, tx));
+            }
+        }
+    }
+
+    txs
 }
 
-impl FeatureExtracter {
-    pub fn new(path: String, opts: &mut Options, f_output: String) -> Self {
-        // opts.optimize_for_point_lookup(0x100000000);
-        // opts.set_optimize_filters_for_hits(true);
-        // opts.optimize_level_style_compaction(0x100000000);
-        // opts.set_memtable_whole_key_filtering(true);
+pub fn fetch_details<D: Datastore>(db: &Database<D>, edges: Vec<Edge>) -> Vec<TransactionInfo> {
+    fetch_edge_details(db, edges)
+        .into_iter()
+        .map(|(_, tx)| tx)
+        .collect()
+}
 
-        let db = RocksdbDatastore::new_db_with_options(path, opts).unwrap();
+/// whether the address has been created by a tx, i.e. its earliest inbound tx is the creation.
+/// Only the details in the earliest block are read when every inbound edge has the derived
+/// `block_number`, otherwise all of them are, a chunk at a time
+fn is_contract<D: Datastore>(db: &Database<D>, id: Uuid) -> bool {
+    let in_q = SpecificVertexQuery::single(id).inbound().unwrap();
+    let in_e = indradb::util::extract_edges(db.get(in_q.clone()).unwrap()).unwrap();
 
-        let mut f_output = csv::Writer::from_path(f_output).unwrap();
+    let numbers_q = in_q
+        .properties()
+        .unwrap()
+        .name(Identifier::new(index::Derived::BlockNumber.name()).unwrap());
+    let numbers: Vec<(Edge, u64)> =
+        indradb::util::extract_edge_properties(db.get(numbers_q).unwrap())
+            .unwrap()
+            .into_iter()
+            .filter_map(|e_props| {
+                let number = e_props.props.first()?.value.0.as_u64()?;
+                Some((e_props.edge, number))
+            })
+            .collect();
+
+    if !in_e.is_empty() && numbers.len() == in_e.len() {
+        let first = numbers.iter().map(|(_, n)| *n).min().unwrap();
+        let earliest = numbers
+            .into_iter()
+            .filter(|(_, n)| *n == first)
+            .map(|(e, _)| e)
+            .collect();
+        return fetch_details(db, earliest).iter().any(|tx| tx.is_create);
+    }
+
+    // (block, created in it) of the earliest block so far
+    let mut earliest: Option<(u64, bool)> = None;
+    for chunk in in_e.chunks(BATCH_SIZE) {
+        for tx in fetch_details(db, chunk.to_vec()) {
+            let number = match tx.block_number {
+                Some(n) => n.as_u64(),
+                None => continue,
+            };
+            earliest = match earliest {
+                Some((first, _)) if first < number => earliest,
+                Some((first, created)) if first == number => Some((first, created || tx.is_create)),
+                _ => Some((number, tx.is_create)),
+            };
+        }
+    }
+    earliest.map_or(false, |(_, created)| created)
+}
+
+/// the features are only defined on eth addresses, the string vertices have none
+fn eth_address(addr: &str) -> Result<H160, String> {
+    H160::from_str(addr).map_err(|_| format!("{} is not an eth address", addr))
+}
+
+pub struct FeatureExtracter<D: Datastore> {
+    db: Arc<Database<D>>,
+    /// None when the records are consumed by the caller, e.g. `serve`
+    f_output: Option<Arc<Mutex<csv::Writer<File>>>>,
+    features: Arc<Vec<&'static Feature>>,
+    /// shared by all seeds, the hubs are the counterparties of most seeds
+    counterparties: Arc<Mutex<HashMap<H160, Counterparty>>>,
+    /// the stop rule of the 2-hop crawl, None when no neighbourhood feature is required
+    neighbourhood: Option<Arc<Stopper>>,
+    concurrency: usize,
+    exact: bool,
+    /// merge the transactions of the whole cluster, see `cluster`
+    collapse: bool,
+}
+
+// derived, it would require the datastore to be cloned too
+impl<D: Datastore> Clone for FeatureExtracter<D> {
+    fn clone(&self) -> Self {
+        FeatureExtracter {
+            db: self.db.clone(),
+            f_output: self.f_output.clone(),
+            features: self.features.clone(),
+            counterparties: self.counterparties.clone(),
+            neighbourhood: self.neighbourhood.clone(),
+            concurrency: self.concurrency,
+            exact: self.exact,
+            collapse: self.collapse,
+        }
+    }
+}
+
+/// write the features of the addresses into the csv
+pub async fn gen_features(
+    path: String,
+    opts: &mut Options,
+    mode: store::OpenMode,
+    f_output: String,
+    features: Vec<&'static Feature>,
+    neighbourhood: Option<Stopper>,
+    concurrency: usize,
+    exact: bool,
+    collapse: bool,
+    v: &mut Vec<String>,
+) {
+    match store::open(path, opts, mode) {
+        Store::Primary(db) => {
+            let mut fe = FeatureExtracter::with_db(
+                Arc::new(db),
+                features,
+                neighbourhood,
+                concurrency,
+                exact,
+                collapse,
+            )
+            .with_output(f_output);
+            fe.gen_subgraph_features(v).await
+        }
+        Store::ReadOnly(db, _) => {
+            let mut fe = FeatureExtracter::with_db(
+                Arc::new(db),
+                features,
+                neighbourhood,
+                concurrency,
+                exact,
+                collapse,
+            )
+            .with_output(f_output);
+            fe.gen_subgraph_features(v).await
+        }
+    }
+}
 
+impl<D: Datastore + Send + Sync + 'static> FeatureExtracter<D> {
+    /// extract on an opened datastore, without the csv output
+    pub fn with_db(
+        db: Arc<Database<D>>,
+        features: Vec<&'static Feature>,
+        neighbourhood: Option<Stopper>,
+        concurrency: usize,
+        exact: bool,
+        collapse: bool,
+    ) -> Self {
         FeatureExtracter {
-            db: Arc::new(db),
+            db,
+            f_output: None,
+            features: Arc::new(features),
+            counterparties: Arc::new(Mutex::new(HashMap::new())),
+            neighbourhood: neighbourhood.map(Arc::new),
+            concurrency,
+            exact,
+            collapse,
+        }
+    }
+
+    /// write the records into the csv rather than handing them to the caller
+    fn with_output(mut self, f_output: String) -> Self {
+        let mut f_output = csv::Writer::from_path(f_output).unwrap();
+        f_output.write_record(self.header()).unwrap();
+        self.f_output = Some(Arc::new(Mutex::new(f_output)));
+        self
+    }
 
-            wei_in_eth: utils::u256_to_bigdecimal(WEI_IN_ETHER),
-            f_output: Arc::new(Mutex::new(f_output)),
+    pub fn header(&self) -> Vec<String> {
+        let mut header = vec!["addr".to_owned()];
+        header.extend(self.features.iter().map(|f| f.name.to_owned()));
+        if self.neighbourhood.is_some() {
+            for f in self.features.iter().filter(|f| !f.needs_counterparties) {
+                for agg in ["mean", "max", "sum"] {
+                    header.push(format!("nbr_{}_{}", agg, f.name));
+                }
+            }
+            header.push("ego_density".to_owned());
+            header.push("ego_clustering".to_owned());
         }
+        header
     }
 
     pub async fn gen_subgraph_features(&mut self, v: &mut Vec<String>) {
@@ -236,16 +849,18 @@ impl FeatureExtracter {
         let mut handles = Vec::new();
         assert_eq!(result.len(), 1);
 
+        // bound the in-flight vertices, each one holds its whole edge set
+        let semaphore = Arc::new(Semaphore::new(self.concurrency));
+
         if let QueryOutputValue::Vertices(vertices) = result[0].clone() {
             assert!(vertices.len() > 0);
             for v in vertices {
-                let db = Arc::clone(&self.db);
-                let f_output = Arc::clone(&self.f_output);
-
-                let wei_in_eth = self.wei_in_eth.clone();
+                let permit = Arc::clone(&semaphore).acquire_owned().await.unwrap();
+                let fe = self.clone();
 
-                handles.push(tokio::spawn(async move {
-                    Self::run_hop(db, &v, f_output, wei_in_eth).await;
+                handles.push(tokio::task::spawn_blocking(move || {
+                    fe.run_hop(&v);
+                    drop(permit);
                 }));
             }
         }
@@ -253,134 +868,356 @@ impl FeatureExtracter {
         for handle in handles {
             handle.await.unwrap();
         }
-    }
 
-    async fn run_hop(
-        db: Arc<Database<RocksdbDatastore>>,
-        v: &Vertex,
-        f_output: Arc<Mutex<csv::Writer<File>>>,
-        wei_in_eth: BigDecimal,
-    ) {
-        // log::debug!("{:?}", v);
+        if let Some(f_output) = &self.f_output {
+            f_output.lock().unwrap().flush().unwrap();
+        }
+    }
 
-        let out_q = SpecificVertexQuery::single(v.id).outbound().unwrap();
-        // .with_property(Identifier::new("details").unwrap()).unwrap();
-        let out_e = db.get(out_q).unwrap();
-
-        // init all lists
-        let mut val_out_list = Vec::new();
-        let mut height_out_list = Vec::new();
-        let mut gas_out_list = Vec::new();
-        let mut gasprice_out_list = Vec::new();
-
-        for edges_list in out_e {
-            let from = v.t.as_str();
-
-            if let QueryOutputValue::Edges(edges) = edges_list {
-                val_out_list = Vec::with_capacity(edges.len());
-                height_out_list = Vec::with_capacity(edges.len());
-                gas_out_list = Vec::with_capacity(edges.len());
-                gasprice_out_list = Vec::with_capacity(edges.len());
-
-                log::debug!("{} has {} outbound edges", from, edges.len());
-
-                for (i, e) in edges.iter().enumerate() {
-                    assert!(e.outbound_id == v.id, "{:?} != {:?}", e, v.id);
-
-                    let q = SpecificEdgeQuery::single(e.clone()).properties().unwrap();
-                    let properties =
-                        indradb::util::extract_edge_properties(db.get(q).unwrap()).unwrap();
-                    let property = &properties[0].props[0];
-                    let json: serde_json::Value = property.value.0.as_ref().clone();
-                    let tx: TransactionInfo = serde_json::from_value(json).unwrap();
-
-                    // let tx =
-                    val_out_list.push(
-                        (utils::u256_to_bigdecimal(tx.value) / &wei_in_eth)
-                            .to_f64()
-                            .unwrap(),
-                    );
-                    height_out_list.push(tx.block_number.unwrap().as_u64());
-                    gas_out_list.push(utils::u256_to_bigdecimal(tx.gas).to_f64().unwrap());
-                    gasprice_out_list.push(
-                        utils::u256_to_bigdecimal(tx.gas_price.unwrap_or(U256::from(0)))
-                            .to_f64()
-                            .unwrap(),
-                    );
-                }
+    fn run_hop(&self, v: &Vertex) {
+        let record = match self.record(v) {
+            Ok(record) => record,
+            Err(e) => {
+                log::error!("skip {}: {}", v.t.as_str(), e);
+                return;
             }
+        };
+        if let Some(f_output) = &self.f_output {
+            f_output.lock().unwrap().write_record(record).unwrap();
         }
+    }
 
-        let in_q = SpecificVertexQuery::single(v.id)
-            .inbound()
-            .unwrap();
-        let in_e = db.get(in_q).unwrap();
-
-        let mut val_in_list = Vec::new();
-        let mut height_in_list = Vec::new();
-        let mut gas_in_list = Vec::new();
-        let mut gasprice_in_list = Vec::new();
-
-        for edges_list in in_e {
-            let to = v.t.as_str();
-
-            if let QueryOutputValue::Edges(edges) = edges_list {
-                val_in_list = Vec::with_capacity(edges.len());
-                height_in_list = Vec::with_capacity(edges.len());
-                gas_in_list = Vec::with_capacity(edges.len());
-                gasprice_in_list = Vec::with_capacity(edges.len());
-
-                log::debug!("{} has {} inbound edges", to, edges.len());
-
-                for (i, e) in edges.iter().enumerate() {
-                    assert!(e.inbound_id == v.id);
-
-                    let q = SpecificEdgeQuery::single(e.clone()).properties().unwrap();
-                    let properties =
-                        indradb::util::extract_edge_properties(db.get(q).unwrap()).unwrap();
-                    let property = &properties[0].props[0];
-                    let json: serde_json::Value = property.value.0.as_ref().clone();
-                    let tx: TransactionInfo = serde_json::from_value(json).unwrap();
-
-                    val_in_list.push(
-                        (utils::u256_to_bigdecimal(tx.value) / &wei_in_eth)
-                            .to_f64()
-                            .unwrap(),
-                    );
-                    height_in_list.push(tx.block_number.unwrap().as_u64());
-                    gas_in_list.push(utils::u256_to_bigdecimal(tx.gas).to_f64().unwrap());
-                    gasprice_in_list.push(
-                        utils::u256_to_bigdecimal(tx.gas_price.unwrap_or(U256::from(0)))
-                            .to_f64()
-                            .unwrap(),
-                    );
-                }
-            }
+    /// the feature values of the vertex, in the order of `header`,
+    /// an error when the vertex or one of its neighbours is not an eth address
+    pub fn record(&self, v: &Vertex) -> Result<Vec<String>, String> {
+        // log::debug!("{:?}", v);
+        let db = &self.db;
+
+        let ids = if self.collapse {
+            cluster::members(db, v.id)
+        } else {
+            vec![v.id]
+        };
+        if ids.len() > 1 {
+            log::debug!("{} has {} cluster members", v.t.as_str(), ids.len());
         }
 
+        // the transactions inside the cluster are dropped
+        let members: HashSet<Uuid> = ids.iter().copied().collect();
+
+        let out_q = SpecificVertexQuery::new(ids.clone()).outbound().unwrap();
+        let mut out_e = indradb::util::extract_edges(db.get(out_q).unwrap()).unwrap();
+        out_e.retain(|e| !members.contains(&e.inbound_id) || e.inbound_id == e.outbound_id);
+        log::debug!("{} has {} outbound edges", v.t.as_str(), out_e.len());
+
+        let in_q = SpecificVertexQuery::new(ids).inbound().unwrap();
+        let mut in_e = indradb::util::extract_edges(db.get(in_q).unwrap()).unwrap();
+        in_e.retain(|e| !members.contains(&e.outbound_id) || e.inbound_id == e.outbound_id);
+        log::debug!("{} has {} inbound edges", v.t.as_str(), in_e.len());
+
         // write_feature start
-        let addr = H160::from_str(v.t.as_str()).unwrap();
+        let mut ctx = AddressContext {
+            addr: eth_address(v.t.as_str())?,
+            txs_in: fetch_details(db, in_e),
+            txs_out: fetch_details(db, out_e),
+            ..Default::default()
+        };
+        if self.features.iter().any(|f| f.needs_counterparties) {
+            self.fill_counterparties(&mut ctx);
+        }
         // let balance = provider
         //     .get_balance(addr, Some(16_200_000.into()))
         //     .await
         //     .unwrap();
-        // let bal = (utils::u256_to_bigdecimal(balance) / &wei_in_eth)
-        //     .to_f64()
-        //     .unwrap();
+        // let bal = utils::decimal_to_f64(&utils::wei_to_ether(balance));
 
-        let addr_feature = AddressFeature::new(
-            addr,
-            // bal,
-            val_in_list,
-            val_out_list,
-            height_in_list,
-            height_out_list,
-            gas_in_list,
-            gas_out_list,
-            gasprice_in_list,
-            gasprice_out_list,
+        let mut record = Vec::with_capacity(self.features.len() + 1);
+        record.push(format!("{:?}", ctx.addr));
+        record.extend(
+            self.features
+                .iter()
+                .map(|f| (f.func)(&ctx).to_output(self.exact)),
         );
-        f_output.lock().unwrap().serialize(addr_feature).unwrap();
-        // write feature end
+        if let Some(stopper) = &self.neighbourhood {
+            record.extend(self.neighbourhood_record(v, stopper)?);
+        }
+        Ok(record)
+    }
+
+    /// crawl the 2-hop subgraph, then aggregate the features of the 1-hop neighbours
+    fn neighbourhood_record(&self, v: &Vertex, stopper: &Stopper) -> Result<Vec<String>, String> {
+        let db = &self.db;
+        let sub = subgraph::crawl(db, &[v.clone()], 2, Direction::Both, stopper);
+
+        let pairs: Vec<(Uuid, Uuid)> = sub
+            .edges
+            .iter()
+            .map(|e| (e.outbound_id, e.inbound_id))
+            .collect();
+        let (density, clustering) = ego_stats(v.id, &pairs);
+
+        // the edges of the stopped neighbours are not crawled, so skip them
+        let mut contexts: HashMap<Uuid, AddressContext> = HashMap::new();
+        for &(a, b) in &pairs {
+            for (n, other) in [(a, b), (b, a)] {
+                if other == v.id
+                    && n != v.id
+                    && !sub.stopped.contains(&n)
+                    && !contexts.contains_key(&n)
+                {
+                    let ctx = AddressContext {
+                        addr: eth_address(sub.address(&n))?,
+                        ..Default::default()
+                    };
+                    contexts.insert(n, ctx);
+                }
+            }
+        }
+        log::debug!("{} has {} neighbours", v.t.as_str(), contexts.len());
+
+        for (e, tx) in fetch_edge_details(db, sub.edges.clone()) {
+            if let Some(ctx) = contexts.get_mut(&e.outbound_id) {
+                ctx.txs_out.push(tx.clone());
+            }
+            if let Some(ctx) = contexts.get_mut(&e.inbound_id) {
+                ctx.txs_in.push(tx);
+            }
+        }
+
+        let mut record = Vec::new();
+        for f in self.features.iter().filter(|f| !f.needs_counterparties) {
+            let values: Vec<f64> = contexts
+                .values()
+                .map(|ctx| (f.func)(ctx).to_f64())
+                .collect();
+            record.extend(aggregate(&values).iter().map(|x| x.to_string()));
+        }
+        record.push(density.to_string());
+        record.push(clustering.to_string());
+        Ok(record)
+    }
+
+    fn fill_counterparties(&self, ctx: &mut AddressContext) {
+        for addr in ctx.unique_counterparties() {
+            let cached = self.counterparties.lock().unwrap().get(&addr).copied();
+            let c = match cached {
+                Some(c) => c,
+                None => {
+                    let id = utils::h160_to_uuid(&addr);
+                    let c = Counterparty {
+                        is_contract: is_contract(&self.db, id),
+                        degree: degree::degree(&self.db, id),
+                    };
+                    self.counterparties.lock().unwrap().insert(addr, c);
+                    c
+                }
+            };
+            ctx.counterparties.insert(addr, c);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(i: u8) -> H160 {
+        H160::repeat_byte(i)
+    }
+
+    fn tx(from: u8, to: u8, ether: u64, block: u64) -> TransactionInfo {
+        TransactionInfo {
+            from: addr(from),
+            to: Some(addr(to)),
+            value: U256::from(ether) * ethers::utils::WEI_IN_ETHER,
+            block_number: Some(block.into()),
+            gas: U256::from(21_000),
+            gas_price: Some(U256::from(10_000_000_000u64)),
+            ..Default::default()
+        }
+    }
+
+    /// 1 receives from 2 and 3, then sends to 4 twice and creates a contract
+    fn fixture() -> AddressContext {
+        let mut create = tx(1, 5, 0, 40);
+        create.to = None;
+        create.is_create = true;
+        create.input = vec![0x60, 0x80].into();
+
+        AddressContext {
+            addr: addr(1),
+            txs_in: vec![tx(2, 1, 3, 10), tx(3, 1, 1, 10)],
+            txs_out: vec![tx(1, 4, 1, 20), tx(1, 4, 2, 30), create],
+            ..Default::default()
+        }
+    }
+
+    fn eval(name: &str, ctx: &AddressContext) -> f64 {
+        let f = FEATURES.iter().find(|f| f.name == name).unwrap();
+        (f.func)(ctx).to_f64()
+    }
+
+    #[test]
+    fn test_select() {
+        assert_eq!(select_features(&[]).unwrap().len(), FEATURES.len());
+
+        let selected = select_features(&["value.*".to_owned(), "timing.freq".to_owned()]).unwrap();
+        assert!(selected
+            .iter()
+            .all(|f| f.family == "value" || f.name == "freq"));
+        assert_eq!(selected.iter().filter(|f| f.family == "timing").count(), 1);
+    }
+
+    #[test]
+    fn test_select_unknown() {
+        let patterns = ["value.*".to_owned(), "value.unknown".to_owned()];
+        assert_eq!(unknown_feature(&patterns), Some(&patterns[1]));
+        assert_eq!(unknown_feature(&patterns[..1]), None);
+        assert!(select_features(&patterns).is_err());
+    }
+
+    #[test]
+    fn test_value() {
+        let ctx = fixture();
+        assert_eq!(eval("sum_val_in", &ctx), 4.);
+        assert_eq!(eval("sum_val_out", &ctx), 3.);
+        assert_eq!(eval("avg_val_in", &ctx), 2.);
+        assert_eq!(eval("avg_val_out", &ctx), 1.);
+        assert_eq!(eval("avg_val_in", &AddressContext::default()), 0.);
+        assert!(eval("gini_val", &AddressContext::default()).is_nan());
+        // 1 and 3 ether in, 0, 1 and 2 out
+        assert_eq!(eval("gini_val_in", &ctx), 0.25);
+        assert!((eval("gini_val_out", &ctx) - 4. / 9.).abs() < 1e-12);
+
+        let f = FEATURES.iter().find(|f| f.name == "avg_val_out").unwrap();
+        assert_eq!((f.func)(&ctx).to_output(true), "1");
+    }
+
+    #[test]
+    fn test_timing() {
+        let ctx = fixture();
+        assert_eq!(eval("count", &ctx), 5.);
+        assert_eq!(eval("freq", &ctx), 5. / 30.);
+        // both inbound txs are in block 10
+        assert_eq!(eval("freq_in", &ctx), 2.);
+        assert_eq!(eval("freq_out", &ctx), 3. / 20.);
+        assert_eq!(eval("freq", &AddressContext::default()), 0.);
+        assert_eq!(eval("in_out_rate", &ctx), 2. / 3.);
+    }
+
+    #[test]
+    fn test_temporal() {
+        let mut ctx = fixture();
+        assert_eq!(eval("first_block", &ctx), 10.);
+        assert_eq!(eval("last_block", &ctx), 40.);
+        assert_eq!(eval("lifetime", &ctx), 30.);
+        assert_eq!(eval("active_blocks", &ctx), 4.);
+        // gaps: 0, 10, 10, 10
+        assert_eq!(eval("gap_mean", &ctx), 7.5);
+        assert_eq!(eval("gap_min", &ctx), 0.);
+        assert_eq!(eval("gap_max", &ctx), 10.);
+        assert!(eval("gap_mean", &AddressContext::default()).is_nan());
+        assert!(eval("burstiness", &ctx) < 0.);
+        assert!(eval("active_days", &ctx).is_nan());
+
+        // two txs at 00:xx of day 0, the others at 01:xx of day 1
+        for (i, tx) in ctx
+            .txs_in
+            .iter_mut()
+            .chain(ctx.txs_out.iter_mut())
+            .enumerate()
+        {
+            let t = if i < 2 { 60 } else { 86_400 + 3_600 };
+            tx.block_timestamp = Some(t.into());
+        }
+        assert_eq!(eval("active_days", &ctx), 2.);
+        let p: f64 = 2. / 5.;
+        let q: f64 = 3. / 5.;
+        assert_eq!(eval("hour_entropy", &ctx), -p * p.log2() - q * q.log2());
+    }
+
+    #[test]
+    fn test_neighbourhood() {
+        assert_eq!(aggregate(&[1., 2., 6.]), [3., 6., 9.]);
+        assert!(aggregate(&[]).iter().all(|x| x.is_nan()));
+
+        let id = |i: u8| Uuid::from_bytes([i; 16]);
+        // seed 0 links 1, 2, 3, and only 1 -> 2 among the neighbours, 4 is 2-hop away
+        let pairs = [
+            (id(0), id(1)),
+            (id(2), id(0)),
+            (id(0), id(3)),
+            (id(0), id(3)),
+            (id(1), id(2)),
+            (id(3), id(4)),
+        ];
+        let (density, clustering) = ego_stats(id(0), &pairs);
+        assert_eq!(density, 2. * 4. / (4. * 3.));
+        assert_eq!(clustering, 1. / 3.);
+
+        let (density, clustering) = ego_stats(id(0), &[]);
+        assert!(density.is_nan());
+        assert!(clustering.is_nan());
+    }
+
+    #[test]
+    fn test_gas() {
+        let mut ctx = fixture();
+        assert_eq!(eval("avg_gas", &ctx), 21_000.);
+        assert_eq!(eval("avg_gasprice", &ctx), 1e10);
+        assert!(eval("avg_gas_in", &AddressContext::default()).is_nan());
+
+        // the creation takes 3 times the gas at 4 times the price
+        ctx.txs_out[2].gas = U256::from(63_000);
+        ctx.txs_out[2].gas_price = Some(U256::from(40_000_000_000u64));
+        assert_eq!(eval("avg_gas", &ctx), 29_400.);
+        assert_eq!(eval("avg_gas_in", &ctx), 21_000.);
+        assert_eq!(eval("avg_gas_out", &ctx), 35_000.);
+        assert_eq!(eval("avg_gasprice", &ctx), 1.6e10);
+        assert_eq!(eval("avg_gasprice_in", &ctx), 1e10);
+        assert_eq!(eval("avg_gasprice_out", &ctx), 2e10);
+    }
+
+    #[test]
+    fn test_counterparty_and_contract() {
+        let mut ctx = fixture();
+        assert_eq!(eval("unique_in", &ctx), 2.);
+        assert_eq!(eval("unique_out", &ctx), 2.);
+        assert_eq!(eval("repeat_rate", &ctx), 1. / 4.);
+        assert_eq!(eval("top1_share_out", &ctx), 1.);
+        assert_eq!(eval("top3_share_in", &ctx), 1.);
+        assert_eq!(eval("top3_share_out", &ctx), 1.);
+
+        let created = get_contract_address(addr(1), U256::zero());
+        for (c, degree, is_contract) in [
+            (addr(2), 1, false),
+            (addr(3), 3, false),
+            (addr(4), 2, true),
+            (created, 2, true),
+        ] {
+            let degree = Degree {
+                degree,
+                ..Default::default()
+            };
+            ctx.counterparties.insert(
+                c,
+                Counterparty {
+                    is_contract,
+                    degree,
+                },
+            );
+        }
+        assert_eq!(eval("contract_rate", &ctx), 0.5);
+        assert_eq!(eval("avg_degree", &ctx), 2.);
+        assert_eq!(eval("max_degree", &ctx), 3.);
+        assert_eq!(eval("std_degree", &ctx), 0.5f64.sqrt());
+        assert_eq!(eval("create_count", &ctx), 1.);
+        assert_eq!(eval("call_rate", &ctx), 1. / 3.);
+
+        // 3 ether to each of 4 and 6, 2 to 7 and 1 to 8
+        ctx.txs_out
+            .extend([tx(1, 6, 3, 50), tx(1, 7, 2, 50), tx(1, 8, 1, 50)]);
+        assert!((eval("top1_share_out", &ctx) - 1. / 3.).abs() < 1e-12);
+        assert!((eval("top3_share_out", &ctx) - 8. / 9.).abs() < 1e-12);
     }
 }
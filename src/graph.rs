@@ -0,0 +1,282 @@
+use std::{
+    cmp::Ordering,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+};
+
+use hashbrown::HashMap;
+use memmap2::Mmap;
+use rocksdb::{IteratorMode, Options, DB};
+use uuid::Uuid;
+
+use crate::{
+    codec, rocks,
+    subgraph::{self, VType},
+};
+
+/// the sorted vertex ids in a memory-mapped file, the position is the vertex index
+pub struct VertexIndex {
+    mmap: Mmap,
+}
+
+impl VertexIndex {
+    /// the keys of `vertices:v2` are already sorted, so just copy them into the file
+    pub fn build(db: &DB, path: &str) -> Self {
+        let mut output = BufWriter::new(File::create(path).unwrap());
+        let iter = db.iterator_cf(db.cf_handle("vertices:v2").unwrap(), IteratorMode::Start);
+        for row in iter {
+            let (k, _) = row.unwrap();
+            output.write_all(&k[..16]).unwrap();
+        }
+        output.flush().unwrap();
+
+        let file = File::open(path).unwrap();
+        let mmap = unsafe { Mmap::map(&file).unwrap() };
+        VertexIndex { mmap }
+    }
+
+    pub fn len(&self) -> usize {
+        self.mmap.len() / 16
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn id(&self, i: usize) -> Uuid {
+        Uuid::from_slice(&self.mmap[i * 16..(i + 1) * 16]).unwrap()
+    }
+
+    pub fn index(&self, id: &Uuid) -> Option<usize> {
+        let (mut lo, mut hi) = (0, self.len());
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            match self.mmap[mid * 16..(mid + 1) * 16].cmp(id.as_bytes()) {
+                Ordering::Less => lo = mid + 1,
+                Ordering::Greater => hi = mid,
+                Ordering::Equal => return Some(mid),
+            }
+        }
+        None
+    }
+}
+
+/// a directed graph whose vertices are numbered from 0 to len - 1
+pub trait Graph {
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// calls f(from, to) on each edge
+    fn for_each_edge(&self, f: &mut dyn FnMut(usize, usize));
+
+    fn out_degrees(&self) -> Vec<u32> {
+        let mut out = vec![0u32; self.len()];
+        self.for_each_edge(&mut |from, _| out[from] += 1);
+        out
+    }
+
+    fn find(&self, addr: &str, v_type: VType) -> Option<usize>;
+
+    fn name(&self, i: usize) -> String;
+}
+
+/// the whole datastore, streamed from the column families
+pub struct RocksGraph {
+    db: DB,
+    index: VertexIndex,
+    /// only the edges in the inclusive block range
+    blocks: Option<(u64, u64)>,
+}
+
+impl RocksGraph {
+    pub fn open(
+        path: String,
+        opts: &Options,
+        index: &str,
+        from_block: Option<u64>,
+        to_block: Option<u64>,
+    ) -> Self {
+        let db = rocks::open_read_only(path, opts);
+        let index = VertexIndex::build(&db, index);
+        let blocks = match (from_block, to_block) {
+            (None, None) => None,
+            (from, to) => Some((from.unwrap_or(0), to.unwrap_or(u64::MAX))),
+        };
+
+        RocksGraph { db, index, blocks }
+    }
+
+    /// a block window needs the `details` of each edge, so scan `edge_properties:v2` instead
+    fn for_each_edge_in_blocks(
+        &self,
+        from_block: u64,
+        to_block: u64,
+        f: &mut dyn FnMut(usize, usize),
+    ) {
+        let mut last: Option<(Uuid, usize)> = None;
+        rocks::for_each_edge_property(&self.db, "details", |out_id, _, in_id, value| {
+            let block = match codec::decode_or_skip(&value).and_then(|tx| tx.block_number) {
+                Some(block) => block.as_u64(),
+                None => return,
+            };
+            if block < from_block || block > to_block {
+                return;
+            }
+
+            // the ids are missing from the index only when the vertices are not written yet
+            let from = match last {
+                Some((id, i)) if id == out_id => i,
+                _ => match self.index.index(&out_id) {
+                    Some(i) => {
+                        last = Some((out_id, i));
+                        i
+                    }
+                    None => return,
+                },
+            };
+            if let Some(to) = self.index.index(&in_id) {
+                f(from, to);
+            }
+        });
+    }
+}
+
+impl Graph for RocksGraph {
+    fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// `reversed_edge_ranges:v2` is grouped by the inbound vertex, so the writes are sequential
+    fn for_each_edge(&self, f: &mut dyn FnMut(usize, usize)) {
+        if let Some((from_block, to_block)) = self.blocks {
+            return self.for_each_edge_in_blocks(from_block, to_block, f);
+        }
+
+        let mut last: Option<(Uuid, usize)> = None;
+        rocks::for_each_edge_range(&self.db, "reversed_edge_ranges:v2", |in_id, _, out_id| {
+            let to = match last {
+                Some((id, i)) if id == in_id => i,
+                _ => {
+                    let i = self.index.index(&in_id).unwrap();
+                    last = Some((in_id, i));
+                    i
+                }
+            };
+            f(self.index.index(&out_id).unwrap(), to);
+        });
+    }
+
+    /// `edge_ranges:v2` is grouped by the outbound vertex
+    fn out_degrees(&self) -> Vec<u32> {
+        let mut out = vec![0u32; self.len()];
+        if self.blocks.is_some() {
+            self.for_each_edge(&mut |from, _| out[from] += 1);
+            return out;
+        }
+
+        let mut last: Option<(Uuid, usize)> = None;
+        rocks::for_each_edge_range(&self.db, "edge_ranges:v2", |out_id, _, _| {
+            let from = match last {
+                Some((id, i)) if id == out_id => i,
+                _ => {
+                    let i = self.index.index(&out_id).unwrap();
+                    last = Some((out_id, i));
+                    i
+                }
+            };
+            out[from] += 1;
+        });
+        out
+    }
+
+    fn find(&self, addr: &str, v_type: VType) -> Option<usize> {
+        self.index.index(&subgraph::to_uuid(addr, v_type))
+    }
+
+    fn name(&self, i: usize) -> String {
+        rocks::get_vertex(&self.db, self.index.id(i)).unwrap()
+    }
+}
+
+/// an exported subgraph, i.e. the edgelist output of `subgraph`
+#[derive(Default)]
+pub struct MemGraph {
+    names: Vec<String>,
+    ids: HashMap<String, usize>,
+    edges: Vec<(usize, usize)>,
+}
+
+impl MemGraph {
+    pub fn add_vertex(&mut self, name: &str) -> usize {
+        if let Some(&i) = self.ids.get(name) {
+            return i;
+        }
+        self.names.push(name.to_owned());
+        self.ids.insert(name.to_owned(), self.names.len() - 1);
+        self.names.len() - 1
+    }
+
+    pub fn add_edge(&mut self, from: &str, to: &str) {
+        let from = self.add_vertex(from);
+        let to = self.add_vertex(to);
+        self.edges.push((from, to));
+    }
+
+    pub fn from_edgelist(filename: &str) -> Self {
+        let mut g = MemGraph::default();
+        let reader = BufReader::new(File::open(filename).unwrap());
+        for line in reader.lines() {
+            let line = line.unwrap();
+            let mut fields = line.split_whitespace();
+            if let (Some(from), Some(to)) = (fields.next(), fields.next()) {
+                g.add_edge(from, to);
+            }
+        }
+        g
+    }
+}
+
+impl Graph for MemGraph {
+    fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    fn for_each_edge(&self, f: &mut dyn FnMut(usize, usize)) {
+        for &(from, to) in &self.edges {
+            f(from, to);
+        }
+    }
+
+    fn find(&self, addr: &str, _v_type: VType) -> Option<usize> {
+        self.ids.get(addr).copied()
+    }
+
+    fn name(&self, i: usize) -> String {
+        self.names[i].clone()
+    }
+}
+
+/// the exported edgelist when given, otherwise the whole datastore
+pub fn open(
+    path: String,
+    opts: &Options,
+    edgelist: Option<String>,
+    index: &str,
+    from_block: Option<u64>,
+    to_block: Option<u64>,
+) -> Box<dyn Graph> {
+    let g: Box<dyn Graph> = match edgelist {
+        Some(edgelist) => {
+            if from_block.is_some() || to_block.is_some() {
+                log::warn!("the block window is ignored on an edgelist");
+            }
+            Box::new(MemGraph::from_edgelist(&edgelist))
+        }
+        None => Box::new(RocksGraph::open(path, opts, index, from_block, to_block)),
+    };
+    log::warn!("{} vertices", g.len());
+    g
+}
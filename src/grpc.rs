@@ -0,0 +1,180 @@
+use std::{net::SocketAddr, pin::Pin, sync::Arc};
+
+use futures::Stream;
+use rocksdb::Options;
+use tokio::sync::mpsc;
+use tonic::{Request, Response, Status};
+
+use crate::{
+    feature,
+    serve::{self, Db, FeatureRequest, Shared, SubgraphRequest},
+    store::{self, OpenMode, Store},
+    subgraph::{Direction, VType},
+};
+
+pub mod proto {
+    tonic::include_proto!("chaingraph");
+}
+
+use proto::chain_graph_server::{ChainGraph, ChainGraphServer};
+
+/// the replies buffered for a slow client
+const STREAM_BUFFER: usize = 1_024;
+
+type ReplyStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+/// run the job on a blocking thread and stream what it sends
+fn stream<T, F>(job: F) -> ReplyStream<T>
+where
+    T: Send + 'static,
+    F: FnOnce(mpsc::Sender<Result<T, Status>>) + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+    tokio::task::spawn_blocking(move || job(tx));
+
+    Box::pin(futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|reply| (reply, rx))
+    }))
+}
+
+fn v_type(v_type: proto::VType) -> VType {
+    match v_type {
+        proto::VType::EthAddress => VType::ETHAddress,
+        proto::VType::String => VType::String,
+    }
+}
+
+fn direction(direction: proto::Direction) -> Direction {
+    match direction {
+        proto::Direction::Both => Direction::Both,
+        proto::Direction::Out => Direction::Out,
+        proto::Direction::In => Direction::In,
+    }
+}
+
+struct ChainGraphService<D: Shared> {
+    db: Db<D>,
+}
+
+#[tonic::async_trait]
+impl<D: Shared> ChainGraph for ChainGraphService<D> {
+    type SubgraphStream = ReplyStream<proto::EdgeReply>;
+    type FeaturesStream = ReplyStream<proto::FeatureReply>;
+
+    async fn subgraph(
+        &self,
+        request: Request<proto::SubgraphRequest>,
+    ) -> Result<Response<Self::SubgraphStream>, Status> {
+        let r = request.into_inner();
+        let v_type = v_type(r.v_type());
+        if let Some(addr) = serve::invalid_address(r.vertices.iter().chain(&r.stop_list), v_type) {
+            return Err(Status::invalid_argument(format!(
+                "invalid address: {}",
+                addr
+            )));
+        }
+        let req = SubgraphRequest {
+            hop: r.hop.max(1) as usize,
+            v_type,
+            direction: direction(r.direction()),
+            vertices: r.vertices,
+            stop_list: r.stop_list,
+            max_degree: r.max_degree,
+            with_props: r.with_props,
+            collapse: r.collapse,
+        };
+
+        let db = self.db.clone();
+        Ok(Response::new(stream(move |tx| {
+            serve::run_subgraph(&db, &req, |line| {
+                let reply = proto::EdgeReply {
+                    from: line.from.to_owned(),
+                    to: line.to.to_owned(),
+                    hash: line.hash.to_owned(),
+                    details: line
+                        .details
+                        .map(|tx| serde_json::to_string(&tx).unwrap())
+                        .unwrap_or_default(),
+                };
+                tx.blocking_send(Ok(reply)).is_ok()
+            })
+        })))
+    }
+
+    async fn features(
+        &self,
+        request: Request<proto::FeaturesRequest>,
+    ) -> Result<Response<Self::FeaturesStream>, Status> {
+        let r = request.into_inner();
+        if let Some(p) = feature::unknown_feature(&r.features) {
+            return Err(Status::invalid_argument(format!("unknown feature: {}", p)));
+        }
+        let addrs = r.vertices.iter().chain(&r.stop_list);
+        if let Some(addr) = serve::invalid_address(addrs, VType::ETHAddress) {
+            return Err(Status::invalid_argument(format!(
+                "invalid address: {}",
+                addr
+            )));
+        }
+        let req = FeatureRequest {
+            vertices: r.vertices,
+            features: r.features,
+            exact: r.exact,
+            neighbourhood: r.neighbourhood,
+            stop_list: r.stop_list,
+            max_degree: r.max_degree,
+            collapse: r.collapse,
+        };
+
+        let db = self.db.clone();
+        Ok(Response::new(stream(move |tx| {
+            serve::run_features(&db, &req, |record| {
+                let record = match record {
+                    Ok(record) => record,
+                    Err(e) => return tx.blocking_send(Err(Status::invalid_argument(e))).is_ok(),
+                };
+                let mut record = record.into_iter();
+                // the first column is the address
+                let (_, address) = record.next().unwrap();
+                let reply = proto::FeatureReply {
+                    address,
+                    features: record.collect(),
+                };
+                tx.blocking_send(Ok(reply)).is_ok()
+            })
+        })))
+    }
+}
+
+/// serve the upstream IndraDB service and the chain queries on the same port,
+/// the writes of the IndraDB service fail unless the datastore is the primary
+pub fn serve(path: String, opts: &mut Options, mode: OpenMode, catch_up: u64, listen: String) {
+    match store::open(path, opts, mode) {
+        Store::Primary(db) => serve_db(Arc::new(db), listen),
+        Store::ReadOnly(db, raw) => {
+            store::keep_catching_up(raw, mode, catch_up);
+            serve_db(Arc::new(db), listen)
+        }
+    }
+}
+
+fn serve_db<D: Shared>(db: Db<D>, listen: String) {
+    let addr: SocketAddr = listen.parse().unwrap();
+    log::warn!("listening on {}", addr);
+
+    let indradb = indradb_proto::ProtoServer::new(indradb_proto::Server::new(db.clone()));
+    let chaingraph = ChainGraphServer::new(ChainGraphService { db });
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async {
+            tonic::transport::Server::builder()
+                .add_service(indradb)
+                .add_service(chaingraph)
+                .serve(addr)
+                .await
+                .unwrap()
+        })
+}
@@ -1,11 +1,228 @@
-use indradb::{RocksdbDatastore, Identifier};
-use rocksdb::Options;
+use ethers::types::U64;
+use indradb::{BulkInsertItem, Database, Edge, Identifier, Json, RocksdbDatastore};
+use rocksdb::{Options, DB};
+
+use crate::{codec, eth_common::TransactionInfo, rocks, store};
+
+/// the key of the indexed property names in `metadata:v2`
+const INDEXED_PROPERTIES: &[u8] = b"indexed_properties";
+
+/// the edges whose derived property is written at once
+const BATCH_SIZE: usize = 100_000;
+
+/// the scalar fields of the edge details which can be copied into their own property
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Derived {
+    Hash,
+    BlockNumber,
+    BlockTimestamp,
+    TransactionIndex,
+    IsCreate,
+    Status,
+}
+
+impl Derived {
+    /// the property name
+    pub fn name(self) -> &'static str {
+        match self {
+            Derived::Hash => "hash",
+            Derived::BlockNumber => "block_number",
+            Derived::BlockTimestamp => "block_timestamp",
+            Derived::TransactionIndex => "transaction_index",
+            Derived::IsCreate => "is_create",
+            Derived::Status => "status",
+        }
+    }
+
+    pub fn extract(self, tx: &TransactionInfo) -> serde_json::Value {
+        let number = |n: Option<U64>| -> serde_json::Value { n.map(|n| n.as_u64()).into() };
+        match self {
+            // the full 0x prefixed hex
+            Derived::Hash => format!("{:?}", tx.hash).into(),
+            Derived::BlockNumber => number(tx.block_number),
+            Derived::BlockTimestamp => number(tx.block_timestamp),
+            Derived::TransactionIndex => number(tx.transaction_index),
+            Derived::IsCreate => tx.is_create.into(),
+            Derived::Status => number(tx.status),
+        }
+    }
+}
+
+/// the names in the metadata, which the indradb fork writes as a bincode set of identifiers
+fn decode_indexed(value: &[u8]) -> Vec<String> {
+    match bincode::deserialize::<Vec<String>>(value) {
+        Ok(names) => names,
+        Err(e) => {
+            log::error!("unreadable indexed properties: {:?}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn encode_indexed(names: &[String]) -> Vec<u8> {
+    bincode::serialize(names).unwrap()
+}
+
+/// the smallest key after every key with the prefix, none when the prefix is all 0xff
+fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
+pub fn indexed_properties(db: &DB) -> Vec<String> {
+    let cf = db.cf_handle("metadata:v2").unwrap();
+    match db.get_cf(cf, INDEXED_PROPERTIES).unwrap() {
+        None => Vec::new(),
+        Some(value) => {
+            let mut names = decode_indexed(&value);
+            names.sort();
+            names
+        }
+    }
+}
+
+/// index the properties which are not yet, e.g. before the writers set them
+pub fn ensure_indexed(
+    datastore: &Database<RocksdbDatastore>,
+    path: &str,
+    opts: &Options,
+    names: &[&str],
+) {
+    let indexed = indexed_properties(&rocks::open_read_only(path, opts));
+    for name in names {
+        if !indexed.iter().any(|n| n == name) {
+            datastore
+                .index_property(Identifier::new(*name).unwrap())
+                .unwrap();
+            log::warn!("indexed {}", name);
+        }
+    }
+}
+
+/// copy the field out of the details of every edge
+fn derive(datastore: &Database<RocksdbDatastore>, path: &str, opts: &Options, derived: Derived) {
+    // the details never change, so a read-only handle beside the writer is enough
+    let db = rocks::open_read_only(path, opts);
+
+    let mut items = Vec::with_capacity(BATCH_SIZE);
+    let mut count = 0;
+    rocks::for_each_edge_property(&db, "details", |out_id, t, in_id, value| {
+        let tx = match codec::decode_or_skip(&value) {
+            Some(tx) => tx,
+            None => return,
+        };
+        let edge = Edge::new(out_id, Identifier::new(t).unwrap(), in_id);
+        items.push(BulkInsertItem::EdgeProperty(
+            edge,
+            Identifier::new(derived.name()).unwrap(),
+            Json::new(derived.extract(&tx)),
+        ));
+        if items.len() == BATCH_SIZE {
+            datastore.bulk_insert(std::mem::take(&mut items)).unwrap();
+            count += BATCH_SIZE;
+            log::debug!("{} edges derived", count);
+        }
+    });
+    count += items.len();
+    datastore.bulk_insert(items).unwrap();
+    log::warn!("{} derived on {} edges", derived.name(), count);
+}
 
 pub fn create_index(
     path: String,
     opts: &mut Options,
-    name: String,
+    name: Option<String>,
+    derived: Option<Derived>,
 ) {
-    let datastore = RocksdbDatastore::new_db_with_options(path, opts).unwrap();
-    datastore.index_property(Identifier::new("details").unwrap());
-}
\ No newline at end of file
+    // the derived property is named after the field
+    let name = match (name, derived) {
+        (Some(name), None) => name,
+        (None, Some(derived)) => derived.name().to_owned(),
+        _ => {
+            log::error!("give either the name or the field to derive");
+            std::process::exit(1);
+        }
+    };
+    let datastore = RocksdbDatastore::new_db_with_options(&path, opts).unwrap();
+
+    if let Some(derived) = derived {
+        derive(&datastore, &path, opts, derived);
+    }
+
+    match datastore.index_property(Identifier::new(&name).unwrap()) {
+        Ok(()) => log::warn!("indexed {}", name),
+        Err(e) => {
+            log::error!("failed to index {}: {:?}", name, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// print the indexed properties with their vertex and edge entries
+pub fn list_indexes(path: String, opts: &Options, mode: store::OpenMode) {
+    let db = store::open_raw(path, opts, mode);
+    println!("name,vertex_entries,edge_entries");
+    for name in indexed_properties(&db) {
+        let prefix = rocks::encode_identifier(&name);
+        println!(
+            "{},{},{}",
+            name,
+            rocks::count_prefix(&db, "vertex_property_values:v2", &prefix),
+            rocks::count_prefix(&db, "edge_property_values:v2", &prefix),
+        );
+    }
+}
+
+/// remove the index entries and the metadata, the property values stay
+pub fn drop_index(path: String, opts: &Options, name: String) {
+    let db = DB::open_cf(opts, path, rocks::CF_NAMES).unwrap();
+    let metadata = db.cf_handle("metadata:v2").unwrap();
+    let names = match db.get_cf(metadata, INDEXED_PROPERTIES).unwrap() {
+        None => Vec::new(),
+        Some(value) => decode_indexed(&value),
+    };
+    if !names.contains(&name) {
+        log::error!("{} is not indexed", name);
+        std::process::exit(1);
+    }
+
+    // utf-8 has no 0xff byte, so the range always ends
+    let prefix = rocks::encode_identifier(&name);
+    let end = prefix_end(&prefix).unwrap();
+    let mut count = 0;
+    for cf_name in ["vertex_property_values:v2", "edge_property_values:v2"] {
+        count += rocks::count_prefix(&db, cf_name, &prefix);
+        // a range tombstone instead of one delete per entry
+        let cf = db.cf_handle(cf_name).unwrap();
+        db.delete_range_cf(cf, &prefix, &end).unwrap();
+    }
+
+    let names: Vec<String> = names.into_iter().filter(|n| *n != name).collect();
+    db.put_cf(metadata, INDEXED_PROPERTIES, encode_indexed(&names))
+        .unwrap();
+    log::warn!("dropped {} with {} entries", name, count);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_indexed_encoding() {
+        let names = vec!["block_number".to_owned(), "cluster".to_owned()];
+        assert_eq!(decode_indexed(&encode_indexed(&names)), names);
+    }
+
+    #[test]
+    fn test_prefix_end() {
+        assert_eq!(prefix_end(b"\x05block"), Some(b"\x05blocl".to_vec()));
+        assert_eq!(prefix_end(&[1, 0xff, 0xff]), Some(vec![2]));
+        assert_eq!(prefix_end(&[0xff]), None);
+    }
+}
@@ -11,29 +11,84 @@ use std::{rc::Rc, sync::Arc};
 use tokio::sync::mpsc::channel;
 use uuid::Uuid;
 
-use crate::{utils::{self, addr_to_uuid}, eth_common::TransactionInfo};
+use crate::{codec, lookup, rocks, utils::{self, addr_to_uuid}, eth_common::TransactionInfo};
+
+/// the key of the linked block range in `metadata:v2`, beside the details format
+const LINKED_BLOCKS_KEY: &[u8] = b"chaingraph:linked_blocks";
+
+/// the block range of the finished `link` runs, both ends included
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct BlockRange {
+    pub first: u64,
+    pub last: u64,
+}
+
+/// None when `link` never finished on the datastore, e.g. one built by `load`
+pub fn read_linked_blocks(db: &rocksdb::DB) -> Option<BlockRange> {
+    let cf = db.cf_handle("metadata:v2").unwrap();
+    db.get_cf(cf, LINKED_BLOCKS_KEY)
+        .unwrap()
+        .map(|value| serde_json::from_slice(&value).unwrap())
+}
+
+/// widen the recorded range with the linked one
+fn write_linked_blocks(db: &rocksdb::DB, linked: BlockRange) {
+    let range = match read_linked_blocks(db) {
+        None => linked,
+        Some(range) => BlockRange {
+            first: range.first.min(linked.first),
+            last: range.last.max(linked.last),
+        },
+    };
+    let cf = db.cf_handle("metadata:v2").unwrap();
+    db.put_cf(cf, LINKED_BLOCKS_KEY, serde_json::to_vec(&range).unwrap())
+        .unwrap();
+}
 
 #[derive(Clone)]
 pub struct Linker {
     db: Arc<Database<RocksdbDatastore>>,
+    /// to record the linked blocks once the datastore is closed
+    path: String,
+    opts: rocksdb::Options,
     provider: Provider<Ws>,
+    format: u64,
+    /// write the tx hash and block number lookup properties
+    lookup: bool,
 }
 
 impl Linker {
-    pub async fn new(ethereum: String, path: String, opts: &mut rocksdb::Options) -> Self {
-        opts.set_disable_auto_compactions(true);
-        opts.set_write_buffer_size(0x80000000); // 2G
+    pub async fn new(
+        ethereum: String,
+        path: String,
+        opts: &mut rocksdb::Options,
+        lookup: bool,
+    ) -> Self {
         // opts.set_enable_blob_files(true);
         // opts.set_enable_blob_gc(false);
         // opts.set_blob_compression_type(rocksdb::DBCompressionType::None);
-        opts.prepare_for_bulk_load();
 
         let provider = Provider::<Ws>::connect(ethereum).await.unwrap();
 
-        let db = RocksdbDatastore::new_db_with_options(path, opts).unwrap();
+        let db = RocksdbDatastore::new_db_with_options(&path, opts).unwrap();
+        let format = codec::get_format(&path, opts);
+        log::warn!("details format: {}", format);
+        // checked once, so the encoding of each edge can't fail
+        if let Err(e) = codec::encode_details(&TransactionInfo::default(), format) {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+        if lookup {
+            lookup::ensure_indexed(&db, &path, opts);
+        }
+
         return Linker {
             db: Arc::new(db),
+            path,
+            opts: opts.clone(),
             provider,
+            format,
+            lookup,
         };
     }
 
@@ -104,6 +159,7 @@ impl Linker {
                                     nonce: tx.nonce,
                                     block_hash: tx.block_hash,
                                     block_number: tx.block_number,
+                                    block_timestamp: Some(block.timestamp.as_u64().into()),
                                     transaction_index: tx.transaction_index,
                                     from: tx.from,
                                     to: tx.to,
@@ -129,8 +185,11 @@ impl Linker {
                                 items.push(indradb::BulkInsertItem::EdgeProperty(
                                     edge.clone(),
                                     Identifier::new("details").unwrap(),
-                                    indradb::Json::new(serde_json::to_value(info).unwrap()),
+                                    codec::encode_details(&info, self.format).unwrap(),
                                 ));
+                                if self.lookup {
+                                    items.extend(lookup::edge_items(&edge, &info));
+                                }
                             }
                             Some(to) => {
                                 let from_id = utils::h160_to_uuid(&tx.from);
@@ -161,6 +220,7 @@ impl Linker {
                                     nonce: tx.nonce,
                                     block_hash: tx.block_hash,
                                     block_number: tx.block_number,
+                                    block_timestamp: Some(block.timestamp.as_u64().into()),
                                     transaction_index: tx.transaction_index,
                                     from: tx.from,
                                     to: tx.to,
@@ -186,8 +246,11 @@ impl Linker {
                                 items.push(indradb::BulkInsertItem::EdgeProperty(
                                     edge.clone(),
                                     Identifier::new("details").unwrap(),
-                                    indradb::Json::new(serde_json::to_value(info).unwrap()),
+                                    codec::encode_details(&info, self.format).unwrap(),
                                 ));
+                                if self.lookup {
+                                    items.extend(lookup::edge_items(&edge, &info));
+                                }
                             }
                         }
                     }
@@ -220,6 +283,17 @@ impl Linker {
             handler.await.unwrap()
         }
 
-        //
+        // every block from 0 to the end is linked now
+        let Linker { db, path, opts, .. } = self;
+        drop(db);
+        let db = rocksdb::DB::open_cf(&opts, &path, rocks::CF_NAMES).unwrap();
+        write_linked_blocks(
+            &db,
+            BlockRange {
+                first: 0,
+                last: end as u64,
+            },
+        );
+        log::warn!("linked the blocks 0 to {}", end);
     }
 }
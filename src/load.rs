@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-use crate::utils;
+use crate::{lookup, utils};
 use csv::StringRecord;
 use indradb::{
     AllEdgeQuery, BulkInsertItem, CountQueryExt, Edge, Identifier, QueryOutputValue,
@@ -10,12 +10,19 @@ use rocksdb::Options;
 
 type Record = HashMap<String, String>;
 
-pub fn bulk_insert(path: String, opts: &mut Options, csv: String, mut fail: usize, bulk: usize) {
-    opts.set_disable_auto_compactions(true);
-    opts.set_write_buffer_size(0x80000000); // 64mb
-    opts.prepare_for_bulk_load();
-
-    let datastore = RocksdbDatastore::new_db_with_options(path, opts).unwrap();
+pub fn bulk_insert(
+    path: String,
+    opts: &mut Options,
+    csv: String,
+    mut fail: usize,
+    bulk: usize,
+    lookup: bool,
+) {
+    let datastore = RocksdbDatastore::new_db_with_options(&path, opts).unwrap();
+    // an edge list has no block number, derive it with `link` or `index create`
+    if lookup {
+        lookup::ensure_indexed(&datastore, &path, opts);
+    }
     log::warn!("start bulk insert");
 
     let e_count: usize = match datastore.get(AllEdgeQuery.count().unwrap()).unwrap()[0] {
@@ -40,7 +47,7 @@ pub fn bulk_insert(path: String, opts: &mut Options, csv: String, mut fail: usiz
         }
 
         let record: Record = result.unwrap();
-        job(record, &mut items);
+        job(record, &mut items, lookup);
         if index % bulk == trigger {
             datastore.bulk_insert(items).unwrap();
             items = Vec::new();
@@ -57,7 +64,7 @@ pub fn bulk_insert(path: String, opts: &mut Options, csv: String, mut fail: usiz
     log::warn!("everything done");
 }
 
-fn job(record: Record, items: &mut Vec<BulkInsertItem>) {
+fn job(record: Record, items: &mut Vec<BulkInsertItem>, lookup: bool) {
     let from = &record["from"];
     let to = &record["to"];
     let hash = &record["edge"];
@@ -72,7 +79,10 @@ fn job(record: Record, items: &mut Vec<BulkInsertItem>) {
     items.push(indradb::BulkInsertItem::Vertex(v));
 
     let edge = Edge::new(from_id, Identifier::new(hash).unwrap(), to_id);
-    items.push(indradb::BulkInsertItem::Edge(edge));
+    items.push(indradb::BulkInsertItem::Edge(edge.clone()));
+    if lookup {
+        items.push(lookup::hash_item(&edge));
+    }
 
 
     // let val = indradb::Json::new(serde_json::Value::from(record["height"].as_str()));
@@ -0,0 +1,165 @@
+use std::io::{self, BufWriter, Write};
+
+use hashbrown::HashMap;
+use indradb::{
+    BulkInsertItem, Database, Datastore, Edge, EdgeWithPropertyValueQuery, Identifier, Json,
+    RocksdbDatastore, SpecificVertexQuery,
+};
+use rocksdb::Options;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    eth_common::TransactionInfo,
+    feature,
+    index::{self, Derived},
+    store::{self, Store},
+};
+
+/// the edge properties written by `load` and `link` for the lookups
+pub const LOOKUPS: [Derived; 2] = [Derived::Hash, Derived::BlockNumber];
+
+/// index the lookup properties before writing them
+pub fn ensure_indexed(datastore: &Database<RocksdbDatastore>, path: &str, opts: &Options) {
+    let names = LOOKUPS.map(|d| d.name());
+    index::ensure_indexed(datastore, path, opts, &names);
+}
+
+/// the `hash` property of an edge without details, e.g. from an edge list
+pub fn hash_item(edge: &Edge) -> BulkInsertItem {
+    BulkInsertItem::EdgeProperty(
+        edge.clone(),
+        Identifier::new(Derived::Hash.name()).unwrap(),
+        Json::new(hash_value(edge.t.as_str())),
+    )
+}
+
+/// the lookup properties of the edge
+pub fn edge_items(edge: &Edge, tx: &TransactionInfo) -> Vec<BulkInsertItem> {
+    LOOKUPS
+        .iter()
+        .map(|d| {
+            BulkInsertItem::EdgeProperty(
+                edge.clone(),
+                Identifier::new(d.name()).unwrap(),
+                Json::new(d.extract(tx)),
+            )
+        })
+        .collect()
+}
+
+/// the `hash` property value, i.e. the 0x prefixed lowercase hex
+pub fn hash_value(hash: &str) -> serde_json::Value {
+    let hash = hash.trim().to_lowercase();
+    let hash = hash.strip_prefix("0x").unwrap_or(&hash);
+    format!("0x{}", hash).into()
+}
+
+fn find_edges<D: Datastore>(
+    db: &Database<D>,
+    derived: Derived,
+    value: serde_json::Value,
+) -> Vec<Edge> {
+    let q =
+        EdgeWithPropertyValueQuery::new(Identifier::new(derived.name()).unwrap(), Json::new(value));
+    match db.get(q) {
+        Ok(output) => indradb::util::extract_edges(output).unwrap(),
+        Err(e) => {
+            log::error!(
+                "{:?}, try `index create --derive {}` first",
+                e,
+                derived.name().replace('_', "-")
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+/// print the edges with the addresses and the details as json lines
+fn print_edges<D: Datastore>(db: &Database<D>, edges: Vec<Edge>) {
+    let mut ids: Vec<Uuid> = edges
+        .iter()
+        .flat_map(|e| [e.outbound_id, e.inbound_id])
+        .collect();
+    ids.sort();
+    ids.dedup();
+    let names: HashMap<Uuid, String> =
+        indradb::util::extract_vertices(db.get(SpecificVertexQuery::new(ids)).unwrap())
+            .unwrap()
+            .into_iter()
+            .map(|v| (v.id, v.t.as_str().to_owned()))
+            .collect();
+
+    let mut details: HashMap<Edge, TransactionInfo> =
+        feature::fetch_edge_details(db, edges.clone())
+            .into_iter()
+            .collect();
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    for e in edges {
+        let line = json!({
+            "from": names[&e.outbound_id],
+            "to": names[&e.inbound_id],
+            "hash": e.t.as_str(),
+            "details": details.remove(&e),
+        });
+        serde_json::to_writer(&mut out, &line).unwrap();
+        out.write_all(b"\n").unwrap();
+    }
+    out.flush().unwrap();
+}
+
+pub fn gen_tx(path: String, opts: &mut Options, mode: store::OpenMode, hashes: Vec<String>) {
+    match store::open(path, opts, mode) {
+        Store::Primary(db) => print_txs(&db, hashes),
+        Store::ReadOnly(db, _) => print_txs(&db, hashes),
+    }
+}
+
+fn print_txs<D: Datastore>(db: &Database<D>, hashes: Vec<String>) {
+    let mut edges = Vec::new();
+    for hash in &hashes {
+        let found = find_edges(db, Derived::Hash, hash_value(hash));
+        if found.is_empty() {
+            log::warn!("{} not found", hash);
+        }
+        edges.extend(found);
+    }
+    print_edges(db, edges);
+}
+
+pub fn gen_block(
+    path: String,
+    opts: &mut Options,
+    mode: store::OpenMode,
+    from: u64,
+    to: Option<u64>,
+) {
+    match store::open(path, opts, mode) {
+        Store::Primary(db) => print_blocks(&db, from, to),
+        Store::ReadOnly(db, _) => print_blocks(&db, from, to),
+    }
+}
+
+fn print_blocks<D: Datastore>(db: &Database<D>, from: u64, to: Option<u64>) {
+    for number in from..=to.unwrap_or(from) {
+        let edges = find_edges(db, Derived::BlockNumber, number.into());
+        log::debug!("block {}: {} edges", number, edges.len());
+        print_edges(db, edges);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_value() {
+        let hash = "0xAbC0000000000000000000000000000000000000000000000000000000000001";
+        assert_eq!(hash_value(hash), hash_value(&hash[2..]));
+        assert_eq!(
+            hash_value(hash),
+            serde_json::Value::from(hash.to_lowercase())
+        );
+    }
+}
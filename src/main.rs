@@ -3,14 +3,32 @@ use std::fs;
 use clap::{arg, command, Parser};
 use rocksdb::DB;
 
+mod cluster;
+mod codec;
+mod community;
+mod components;
+mod degree;
 mod dump;
 mod eth_common;
+mod export;
 mod feature;
+mod graph;
+mod grpc;
 mod index;
 mod link;
 mod load;
+mod lookup;
+mod motifs;
+mod peel;
+mod rank;
 mod repair;
+mod rocks;
+mod serve;
+mod snapshot;
+mod stats;
+mod store;
 mod subgraph;
+mod tune;
 mod unique;
 mod utils;
 
@@ -25,6 +43,18 @@ struct Args {
     /// Rocksdb Path
     #[arg(short, long, default_value = "./rocks")]
     rocks: String,
+
+    /// how the query commands open the rocksdb, read-only and secondary can run while load or link is writing
+    #[arg(long, value_enum, default_value = "primary")]
+    open_mode: store::OpenMode,
+
+    /// the rocksdb tuning, bulk-load for load and link, point-query for the queries and scan for the rest by default
+    #[arg(long, value_enum)]
+    profile: Option<tune::Profile>,
+
+    /// a toml file which overrides the profile and its options
+    #[arg(long)]
+    config: Option<String>,
 }
 
 #[derive(clap::Subcommand, Debug)]
@@ -42,6 +72,14 @@ enum Action {
         /// Bulk insert number
         #[arg(short, long, default_value_t = 10_000)]
         bulk: usize,
+
+        /// skip the tx hash lookup property
+        #[arg(long)]
+        no_lookup_index: bool,
+
+        /// collect the rocksdb statistics and save them into the file at the end
+        #[arg(long)]
+        statistics: Option<String>,
     },
     /// load the subgraph from the graph database
     Subgraph {
@@ -76,9 +114,295 @@ enum Action {
         /// carry props rather than txhash
         #[arg(long, value_delimiter = ',')]
         with_props: Vec<String>,
+
+        /// provide a file which contains the verteies that are never expanded
+        #[arg(long)]
+        stop_list: Option<String>,
+
+        /// never expand the verteies whose degree exceeds it, 0 means no limit
+        #[arg(long, default_value_t = 0)]
+        max_degree: u64,
+
+        /// output the node list into the file
+        #[arg(long)]
+        nodes_output: Option<String>,
+
+        /// replace the clustered verteies with their cluster id
+        #[arg(long, default_value_t = false)]
+        collapse: bool,
+
+        /// run louvain on the subgraph and write the community id into the node list
+        #[arg(long, default_value_t = false)]
+        annotate_communities: bool,
+
+        /// the edge weight of the community detection
+        #[arg(value_enum, long, default_value_t = community::Weight::Count)]
+        community_weight: community::Weight,
+
+        /// output the exact decimal strings of the props rather than floats
+        #[arg(long, default_value_t = false)]
+        exact: bool,
+    },
+    /// count the in/out degree of the vertices
+    Degree {
+        /// contains the verteies
+        #[arg(short, long)]
+        vertices: Vec<String>,
+
+        /// or privide a file which contains the verteies
+        #[arg(short, long)]
+        input: Option<String>,
+
+        /// output filename
+        #[arg(short, long, default_value = "degree.csv")]
+        output: String,
+
+        /// the vertex type of the input
+        #[arg(value_enum, long, default_value_t = subgraph::VType::ETHAddress)]
+        v_type: subgraph::VType,
+
+        /// only count the edges from this block
+        #[arg(long)]
+        from_block: Option<u64>,
+
+        /// only count the edges until this block
+        #[arg(long)]
+        to_block: Option<u64>,
+    },
+    /// rank the vertices with pagerank, personalised when seeds are given
+    Rank {
+        /// the seed verteies
+        #[arg(short, long)]
+        vertices: Vec<String>,
+
+        /// or privide a file which contains the seed verteies
+        #[arg(short, long)]
+        input: Option<String>,
+
+        /// the vertex type of the seeds
+        #[arg(value_enum, long, default_value_t = subgraph::VType::ETHAddress)]
+        v_type: subgraph::VType,
+
+        /// rank an exported edgelist instead of the whole graph
+        #[arg(long)]
+        edgelist: Option<String>,
+
+        /// the on-disk vertex index file
+        #[arg(long, default_value = "vertex.index")]
+        index: String,
+
+        /// output filename
+        #[arg(short, long, default_value = "rank.csv")]
+        output: String,
+
+        /// only output the top n vertices
+        #[arg(long, default_value_t = 1000)]
+        top: usize,
+
+        #[arg(long, default_value_t = 0.85)]
+        damping: f64,
+
+        #[arg(long, default_value_t = 50)]
+        iterations: usize,
+
+        /// stop when the l1 change is less than this
+        #[arg(long, default_value_t = 1e-9)]
+        tolerance: f64,
+    },
+    /// label the weakly connected components
+    Components {
+        /// use an exported edgelist instead of the whole graph
+        #[arg(long)]
+        edgelist: Option<String>,
+
+        /// the on-disk vertex index file
+        #[arg(long, default_value = "vertex.index")]
+        index: String,
+
+        /// only use the edges from this block
+        #[arg(long)]
+        from_block: Option<u64>,
+
+        /// only use the edges until this block
+        #[arg(long)]
+        to_block: Option<u64>,
+
+        /// output filename
+        #[arg(short, long, default_value = "components.csv")]
+        output: String,
+
+        /// skip the components smaller than this
+        #[arg(long, default_value_t = 1)]
+        min_size: usize,
+    },
+    /// find the temporal cycles and the fan-in/fan-out patterns
+    Motifs {
+        /// the seed verteies of the subgraph, or scan the block window when absent
+        #[arg(short, long)]
+        vertices: Vec<String>,
+
+        /// or privide a file which contains the seed verteies
+        #[arg(short, long)]
+        input: Option<String>,
+
+        /// the vertex type of the input
+        #[arg(value_enum, long, default_value_t = subgraph::VType::ETHAddress)]
+        v_type: subgraph::VType,
+
+        /// the hop of the subgraph
+        #[arg(long, default_value_t = 2)]
+        hop: usize,
+
+        /// provide a file which contains the verteies that are never expanded
+        #[arg(long)]
+        stop_list: Option<String>,
+
+        /// never expand the verteies whose degree exceeds it, 0 means no limit
+        #[arg(long, default_value_t = 0)]
+        max_degree: u64,
+
+        /// only use the edges from this block
+        #[arg(long)]
+        from_block: Option<u64>,
+
+        /// only use the edges until this block
+        #[arg(long)]
+        to_block: Option<u64>,
+
+        /// the shortest cycle
+        #[arg(long, default_value_t = 2)]
+        min_len: usize,
+
+        /// the longest cycle
+        #[arg(long, default_value_t = 5)]
+        max_len: usize,
+
+        /// the relative value difference allowed against the first transaction
+        #[arg(long, default_value_t = 0.1)]
+        tolerance: f64,
+
+        /// the most blocks a cycle may span
+        #[arg(long)]
+        max_span: Option<u64>,
+
+        /// the most paths the cycle search extends from each transaction, 0 means no limit
+        #[arg(long, default_value_t = 100000)]
+        max_steps: usize,
+
+        /// the least distinct counterparties of a fan-in/fan-out
+        #[arg(long, default_value_t = 5)]
+        fan: usize,
+
+        /// the blocks a fan-in/fan-out may span
+        #[arg(long, default_value_t = 100)]
+        fan_window: u64,
+
+        /// output filename
+        #[arg(short, long, default_value = "motifs.csv")]
+        output: String,
+    },
+    /// follow the peel chains from the seeds
+    Peel {
+        /// the seed verteies
+        #[arg(short, long)]
+        vertices: Vec<String>,
+
+        /// or privide a file which contains the seed verteies
+        #[arg(short, long)]
+        input: Option<String>,
+
+        /// the vertex type of the input
+        #[arg(value_enum, long, default_value_t = subgraph::VType::ETHAddress)]
+        v_type: subgraph::VType,
+
+        /// only follow the transactions from this block
+        #[arg(long)]
+        from_block: Option<u64>,
+
+        /// the least share of what an address received which the dominant transaction forwards
+        #[arg(long, default_value_t = 0.8)]
+        min_ratio: f64,
+
+        /// skip the chains shorter than this
+        #[arg(long, default_value_t = 3)]
+        min_len: usize,
+
+        /// stop following after this many hops
+        #[arg(long, default_value_t = 100)]
+        max_len: usize,
+
+        /// output filename
+        #[arg(short, long, default_value = "peel.csv")]
+        output: String,
+    },
+    /// serve the queries over http
+    Serve {
+        /// the listening address
+        #[arg(short, long, default_value = "127.0.0.1:8080")]
+        listen: String,
+
+        /// catch the secondary instance up with the writer every n seconds, 0 means never
+        #[arg(long, default_value_t = 60)]
+        catch_up: u64,
+    },
+    /// serve the indradb and the chain queries over grpc
+    Grpc {
+        /// the listening address
+        #[arg(short, long, default_value = "127.0.0.1:27615")]
+        listen: String,
+
+        /// catch the secondary instance up with the writer every n seconds, 0 means never
+        #[arg(long, default_value_t = 60)]
+        catch_up: u64,
+    },
+    /// take a consistent copy of the rocksdb, a hard linked checkpoint or an incremental backup
+    Snapshot {
+        /// the checkpoint directory, which must not exist, or the backup directory with --backup
+        #[arg(short, long)]
+        output: String,
+        /// add a backup into the backup directory rather than creating a checkpoint
+        #[arg(long)]
+        backup: bool,
+        /// keep the latest n backups, 0 means keeping all
+        #[arg(long, default_value_t = 0)]
+        keep: usize,
+    },
+    /// restore the rocksdb from a checkpoint or a backup directory
+    Restore {
+        /// the checkpoint or the backup directory
+        #[arg(short, long)]
+        input: String,
+        /// the backup to restore, the latest by default
+        #[arg(long)]
+        backup_id: Option<u32>,
+        /// only list the backups, or print the checkpoint metadata
+        #[arg(long)]
+        list: bool,
+    },
+    /// dump the graph database as json lines
+    Dump {
+        /// the column families, e.g. vertices:v2,edge_ranges:v2, all by default
+        #[arg(long, value_delimiter = ',', value_parser = clap::builder::PossibleValuesParser::new(rocks::CF_NAMES))]
+        cf: Vec<String>,
+        /// print the keys and the values in hex rather than decoding them
+        #[arg(long)]
+        raw: bool,
+        /// write the portable dump into the directory instead, for restore-from-dump
+        #[arg(long, conflicts_with_all = ["cf", "raw"])]
+        export: Option<String>,
+        /// the lines of each gzipped chunk of the portable dump
+        #[arg(long, default_value_t = 1_000_000)]
+        chunk_size: usize,
+    },
+    /// load a portable dump written by dump --export
+    RestoreFromDump {
+        /// the dump directory
+        #[arg(short, long)]
+        input: String,
+        /// the items of each bulk insert
+        #[arg(long, default_value_t = 100_000)]
+        batch_size: usize,
     },
-    /// dump the graph database as json
-    Dump {},
     /// repair the rocksdb
     Repair {},
     /// compact the rocksdb
@@ -96,6 +420,56 @@ enum Action {
         /// output filename
         #[arg(short, long, default_value = "features.csv")]
         feature_output: String,
+
+        /// max count of the verteies extracted at the same time
+        #[arg(short, long, default_value_t = 0)]
+        concurrency: usize,
+
+        /// output the exact decimal strings rather than floats
+        #[arg(long)]
+        exact: bool,
+
+        /// the selected features, e.g. value.*,timing.freq, all features by default
+        #[arg(long, value_delimiter = ',')]
+        features: Vec<String>,
+
+        /// also aggregate the features of the neighbours in the 2-hop subgraph
+        #[arg(long)]
+        neighbourhood: bool,
+
+        /// provide a file which contains the verteies that are never expanded in the 2-hop crawl
+        #[arg(long)]
+        stop_list: Option<String>,
+
+        /// never expand the verteies whose degree exceeds it in the 2-hop crawl, 0 means no limit
+        #[arg(long, default_value_t = 0)]
+        max_degree: u64,
+
+        /// extract the features of the whole cluster of each vertex
+        #[arg(long, default_value_t = false)]
+        collapse: bool,
+    },
+    /// cluster the addresses with the heuristics and save the cluster id on the verteies
+    Cluster {
+        /// the heuristics to run
+        #[arg(value_enum, long, value_delimiter = ',', default_values_t = [cluster::Heuristic::Deposit, cluster::Heuristic::Airdrop, cluster::Heuristic::Approval])]
+        heuristics: Vec<cluster::Heuristic>,
+
+        /// a file which contains the exchange hot wallets, for the deposit heuristic
+        #[arg(long)]
+        exchanges: Option<String>,
+
+        /// a file which contains the airdrop contracts, for the airdrop heuristic
+        #[arg(long)]
+        airdrops: Option<String>,
+
+        /// the least share of the outbound edges to the exchanges of a deposit address
+        #[arg(long, default_value_t = 0.9)]
+        deposit_ratio: f64,
+
+        /// also output the clusters into the file
+        #[arg(short, long)]
+        output: Option<String>,
     },
     /// link with a ethereum node
     Link {
@@ -110,12 +484,63 @@ enum Action {
         /// the ending of the link sync
         #[arg(long, default_value_t = 0)]
         end: usize,
+
+        /// skip the tx hash and block number lookup properties
+        #[arg(long)]
+        no_lookup_index: bool,
+
+        /// collect the rocksdb statistics and save them into the file at the end
+        #[arg(long)]
+        statistics: Option<String>,
     },
-    /// create an index on the property
-    Index {
-        /// field name
+    /// print the counts, the sizes and the layout of the datastore as json
+    Stats {
+        /// count the keys rather than using the rocksdb estimates
+        #[arg(long)]
+        exact: bool,
+
+        /// scan the details for the ingested block range
+        #[arg(long)]
+        blocks: bool,
+
+        /// scan the edge ranges for the degree histogram and the hubs
+        #[arg(long)]
+        degrees: bool,
+
+        /// the count of the hubs
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+    },
+    /// print the transactions of the hashes as json lines
+    Tx {
+        /// the transaction hashes, with or without 0x
+        #[arg(value_delimiter = ',')]
+        hashes: Vec<String>,
+
+        /// or privide a file which contains the hashes
         #[arg(short, long)]
-        name: String,
+        input: Option<String>,
+    },
+    /// print the transactions in the blocks as json lines
+    Block {
+        /// the block number
+        #[arg(short, long)]
+        number: u64,
+
+        /// the last block number of a range, inclusive
+        #[arg(long)]
+        to: Option<u64>,
+    },
+    /// rewrite the edge details into the format
+    Convert {
+        /// the target format
+        #[arg(value_enum, short, long, default_value_t = codec::Format::Compact)]
+        format: codec::Format,
+    },
+    /// create, list or drop the property indexes
+    Index {
+        #[command(subcommand)]
+        action: IndexAction,
     },
     /// extract unique vertices/addresses from the file
     Edgelist {
@@ -125,6 +550,48 @@ enum Action {
     },
 }
 
+impl Action {
+    /// the tuning profile when neither the flag nor the config picks one
+    fn profile(&self) -> tune::Profile {
+        match self {
+            Action::Load { .. } | Action::Link { .. } | Action::RestoreFromDump { .. } => {
+                tune::Profile::BulkLoad
+            }
+            Action::Subgraph { .. }
+            | Action::Degree { .. }
+            | Action::Motifs { .. }
+            | Action::Peel { .. }
+            | Action::Serve { .. }
+            | Action::Grpc { .. }
+            | Action::Feature { .. }
+            | Action::Tx { .. }
+            | Action::Block { .. } => tune::Profile::PointQuery,
+            _ => tune::Profile::Scan,
+        }
+    }
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum IndexAction {
+    /// create an index on the property
+    Create {
+        /// field name
+        #[arg(short, long, required_unless_present = "derive", conflicts_with = "derive")]
+        name: Option<String>,
+        /// copy the field out of the edge details into the property of the same name first
+        #[arg(long, value_enum)]
+        derive: Option<index::Derived>,
+    },
+    /// list the indexed properties with their entries
+    List {},
+    /// drop the index on the property, the values stay
+    Drop {
+        /// field name
+        #[arg(short, long)]
+        name: String,
+    },
+}
+
 #[derive(clap::Subcommand, Debug)]
 enum EdgelistAction {
     Unique { 
@@ -137,32 +604,28 @@ fn main() {
     pretty_env_logger::init_timed();
     let args = Args::parse();
 
-    let mut opts = rocksdb::Options::default();
-    opts.create_if_missing(true);
-    opts.increase_parallelism(num_cpus::get().try_into().unwrap());
-    opts.set_compaction_style(rocksdb::DBCompactionStyle::Level);
-
-    opts.set_max_write_buffer_number(3);
-    opts.set_target_file_size_base(0x4000000); // 64mb
-    opts.set_level_zero_file_num_compaction_trigger(8);
-    opts.set_level_zero_slowdown_writes_trigger(17);
-    opts.set_level_zero_stop_writes_trigger(24);
-    opts.set_num_levels(4);
-    opts.set_max_bytes_for_level_base(536_870_912); // 512mb
-    opts.set_max_bytes_for_level_multiplier(8.0);
-    // opts.set_enable_blob_files(true);
-    // opts.set_min_blob_size(0x0); // all in blob
-    // opts.enable_statistics();
-
-    // let v_count: usize = match datastore.get(AllVertexQuery.count().unwrap()).unwrap()[0] {
-    //     QueryOutputValue::Count(count) => count.try_into().unwrap(),
-    //     _ => todo!(),
-    // };
-    // log::warn!("all node: {:?}", v_count);
+    let config = args.config.as_deref().map(tune::Config::load).unwrap_or_default();
+    let profile = args
+        .profile
+        .or(config.profile)
+        .unwrap_or_else(|| args.action.profile());
+    let mut opts = tune::options(profile, &config.tuning(profile));
 
     match args.action {
-        Action::Load { csv, fail, bulk } => {
-            load::bulk_insert(args.rocks, &mut opts, csv, fail, bulk)
+        Action::Load {
+            csv,
+            fail,
+            bulk,
+            no_lookup_index,
+            statistics,
+        } => {
+            if statistics.is_some() {
+                stats::enable_statistics(&mut opts);
+            }
+            load::bulk_insert(args.rocks, &mut opts, csv, fail, bulk, !no_lookup_index);
+            if let Some(file) = statistics {
+                stats::save_statistics(&opts, &file);
+            }
         }
         Action::Subgraph {
             mut vertices,
@@ -173,15 +636,25 @@ fn main() {
             v_type,
             direction,
             with_props,
+            stop_list,
+            max_degree,
+            nodes_output,
+            collapse,
+            annotate_communities,
+            community_weight,
+            exact,
         } => {
             if let Some(input) = input {
                 let content = fs::read_to_string(input).unwrap();
                 vertices.extend(content.split_whitespace().map(|s| s.to_string()));
             }
 
+            let stopper = subgraph::Stopper::new(stop_list, max_degree, v_type);
+
             subgraph::gen_subgraph(
                 args.rocks,
                 &mut opts,
+                args.open_mode,
                 &mut vertices,
                 hop,
                 output,
@@ -189,65 +662,320 @@ fn main() {
                 v_type,
                 direction,
                 with_props,
+                stopper,
+                nodes_output,
+                collapse,
+                annotate_communities.then_some(community_weight),
+                exact,
+            )
+        }
+        Action::Degree {
+            mut vertices,
+            input,
+            output,
+            v_type,
+            from_block,
+            to_block,
+        } => {
+            if let Some(input) = input {
+                let content = fs::read_to_string(input).unwrap();
+                vertices.extend(content.split_whitespace().map(|s| s.to_string()));
+            }
+
+            degree::gen_degrees(
+                args.rocks,
+                &mut opts,
+                args.open_mode,
+                &mut vertices,
+                output,
+                v_type,
+                from_block,
+                to_block,
+            )
+        }
+        Action::Rank {
+            mut vertices,
+            input,
+            v_type,
+            edgelist,
+            index,
+            output,
+            top,
+            damping,
+            iterations,
+            tolerance,
+        } => {
+            if let Some(input) = input {
+                let content = fs::read_to_string(input).unwrap();
+                vertices.extend(content.split_whitespace().map(|s| s.to_string()));
+            }
+
+            rank::gen_rank(
+                args.rocks,
+                &opts,
+                &mut vertices,
+                v_type,
+                edgelist,
+                index,
+                output,
+                top,
+                damping,
+                iterations,
+                tolerance,
             )
         }
-        Action::Dump {} => dump::json(args.rocks, &opts),
+        Action::Components {
+            edgelist,
+            index,
+            from_block,
+            to_block,
+            output,
+            min_size,
+        } => components::gen_components(
+            args.rocks, &opts, edgelist, index, from_block, to_block, output, min_size,
+        ),
+        Action::Motifs {
+            mut vertices,
+            input,
+            v_type,
+            hop,
+            stop_list,
+            max_degree,
+            from_block,
+            to_block,
+            min_len,
+            max_len,
+            tolerance,
+            max_span,
+            max_steps,
+            fan,
+            fan_window,
+            output,
+        } => {
+            if let Some(input) = input {
+                let content = fs::read_to_string(input).unwrap();
+                vertices.extend(content.split_whitespace().map(|s| s.to_string()));
+            }
+
+            let stopper = subgraph::Stopper::new(stop_list, max_degree, v_type);
+            motifs::gen_motifs(
+                args.rocks,
+                &mut opts,
+                args.open_mode,
+                &mut vertices,
+                v_type,
+                hop,
+                stopper,
+                from_block,
+                to_block,
+                min_len,
+                max_len,
+                tolerance,
+                max_span,
+                max_steps,
+                fan,
+                fan_window,
+                output,
+            )
+        }
+        Action::Peel {
+            mut vertices,
+            input,
+            v_type,
+            from_block,
+            min_ratio,
+            min_len,
+            max_len,
+            output,
+        } => {
+            if let Some(input) = input {
+                let content = fs::read_to_string(input).unwrap();
+                vertices.extend(content.split_whitespace().map(|s| s.to_string()));
+            }
+
+            peel::gen_peel_chains(
+                args.rocks,
+                &mut opts,
+                args.open_mode,
+                &mut vertices,
+                v_type,
+                from_block,
+                min_ratio,
+                min_len,
+                max_len,
+                output,
+            )
+        }
+        Action::Serve { listen, catch_up } => {
+            serve::serve(args.rocks, &mut opts, args.open_mode, catch_up, listen)
+        }
+        Action::Grpc { listen, catch_up } => {
+            grpc::serve(args.rocks, &mut opts, args.open_mode, catch_up, listen)
+        }
+        Action::Snapshot {
+            output,
+            backup,
+            keep,
+        } => snapshot::snapshot(args.rocks, &opts, output, backup, keep),
+        Action::Restore {
+            input,
+            backup_id,
+            list,
+        } => {
+            if let Err(e) = snapshot::restore(args.rocks, input, backup_id, list) {
+                log::error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        Action::Dump {
+            cf,
+            raw,
+            export,
+            chunk_size,
+        } => {
+            let exported = match export {
+                Some(dir) => export::export(args.rocks, &opts, args.open_mode, dir, chunk_size),
+                None => Ok(dump::json(args.rocks, &opts, args.open_mode, cf, raw)),
+            };
+            if let Err(e) = exported {
+                log::error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        Action::RestoreFromDump { input, batch_size } => {
+            if let Err(e) = export::import(args.rocks, &mut opts, input, batch_size) {
+                log::error!("{}", e);
+                std::process::exit(1);
+            }
+        }
         Action::Repair {} => repair::repair_db(args.rocks, &opts),
         Action::Compact {} => {
-            const CF_NAMES: [&str; 8] = [
-                "vertices:v2",
-                "edge_ranges:v2",
-                "reversed_edge_ranges:v2",
-                "vertex_properties:v2",
-                "edge_properties:v2",
-                "vertex_property_values:v2",
-                "edge_property_values:v2",
-                "metadata:v2",
-            ];
-            let db = DB::open_cf(&opts, args.rocks, CF_NAMES).unwrap();
+            let db = DB::open_cf(&opts, args.rocks, rocks::CF_NAMES).unwrap();
             db.compact_range::<Vec<u8>, Vec<u8>>(None, None);
         }
         Action::Feature {
             mut vertices,
             input,
             feature_output,
+            concurrency,
+            exact,
+            features,
+            neighbourhood,
+            stop_list,
+            max_degree,
+            collapse,
         } => {
+            let concurrency = if concurrency == 0 {
+                num_cpus::get()
+            } else {
+                concurrency
+            };
+
             if let Some(input) = input {
                 let content = fs::read_to_string(input).unwrap();
                 vertices.extend(content.split_whitespace().map(|s| s.to_string()));
             }
+            let features = feature::select_features(&features).unwrap_or_else(|e| {
+                log::error!("{}", e);
+                std::process::exit(1);
+            });
 
             tokio::runtime::Builder::new_multi_thread()
                 .enable_all()
                 .build()
                 .unwrap()
-                .block_on(async {
-                    let mut fe =
-                        feature::FeatureExtracter::new(args.rocks, &mut opts, feature_output);
-                    fe.gen_subgraph_features(&mut vertices).await
-                })
+                .block_on(feature::gen_features(
+                    args.rocks,
+                    &mut opts,
+                    args.open_mode,
+                    feature_output,
+                    features,
+                    neighbourhood.then(|| {
+                        subgraph::Stopper::new(stop_list, max_degree, subgraph::VType::ETHAddress)
+                    }),
+                    concurrency,
+                    exact,
+                    collapse,
+                    &mut vertices,
+                ))
         }
+        Action::Cluster {
+            heuristics,
+            exchanges,
+            airdrops,
+            deposit_ratio,
+            output,
+        } => cluster::gen_clusters(
+            args.rocks,
+            &mut opts,
+            heuristics,
+            exchanges,
+            airdrops,
+            deposit_ratio,
+            output,
+        ),
         Action::Link {
             ethereum,
             thread_count,
             end,
-        } => tokio::runtime::Builder::new_multi_thread()
-            .enable_all()
-            .build()
-            .unwrap()
-            .block_on(async {
-                let thread_count = if thread_count <= 0 {
-                    num_cpus::get()
-                } else {
-                    thread_count
-                };
-
-                let linker = link::Linker::new(ethereum, args.rocks, &mut opts).await;
-                linker.sync(thread_count, end).await;
-            }),
-        Action::Index { name } => {
-            index::create_index(args.rocks, &mut opts, name);
+            no_lookup_index,
+            statistics,
+        } => {
+            if statistics.is_some() {
+                stats::enable_statistics(&mut opts);
+            }
+            tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+                .block_on(async {
+                    let thread_count = if thread_count <= 0 {
+                        num_cpus::get()
+                    } else {
+                        thread_count
+                    };
+
+                    let linker =
+                        link::Linker::new(ethereum, args.rocks, &mut opts, !no_lookup_index)
+                            .await;
+                    linker.sync(thread_count, end).await;
+                });
+            if let Some(file) = statistics {
+                stats::save_statistics(&opts, &file);
+            }
+        }
+        Action::Tx { mut hashes, input } => {
+            if let Some(input) = input {
+                let content = fs::read_to_string(input).unwrap();
+                hashes.extend(content.split_whitespace().map(|s| s.to_string()));
+            }
+            lookup::gen_tx(args.rocks, &mut opts, args.open_mode, hashes)
+        }
+        Action::Block { number, to } => {
+            lookup::gen_block(args.rocks, &mut opts, args.open_mode, number, to)
         }
+        Action::Stats {
+            exact,
+            blocks,
+            degrees,
+            top,
+        } => stats::stats(
+            args.rocks,
+            &opts,
+            args.open_mode,
+            exact,
+            blocks,
+            degrees,
+            top,
+        ),
+        Action::Convert { format } => codec::convert(args.rocks, &mut opts, format),
+        Action::Index { action } => match action {
+            IndexAction::Create { name, derive } => {
+                index::create_index(args.rocks, &mut opts, name, derive)
+            }
+            IndexAction::List {} => index::list_indexes(args.rocks, &opts, args.open_mode),
+            IndexAction::Drop { name } => index::drop_index(args.rocks, &opts, name),
+        },
         Action::Edgelist { action } => match action {
             EdgelistAction::Unique { filename } => unique::extract_unique_vertices(filename),
         },
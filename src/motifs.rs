@@ -0,0 +1,466 @@
+use hashbrown::{HashMap, HashSet};
+use indradb::{Database, Datastore, SpecificVertexQuery};
+use rocksdb::Options;
+use uuid::Uuid;
+
+use crate::{
+    codec, feature, rocks,
+    store::{self, Store},
+    subgraph::{self, Direction, Stopper, VType},
+    utils,
+};
+
+/// a transaction between the numbered vertices
+#[derive(Debug, Clone)]
+pub struct Transfer {
+    pub from: usize,
+    pub to: usize,
+    pub hash: String,
+    pub block: u64,
+    /// in ether
+    pub value: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Motif {
+    /// `cycle<len>`, `fan_in` or `fan_out`
+    pub kind: String,
+    /// the cycle in order, or the center followed by the counterparties
+    pub vertices: Vec<usize>,
+    pub hashes: Vec<String>,
+    pub first_block: u64,
+    pub last_block: u64,
+    pub value: f64,
+}
+
+impl Motif {
+    fn new(kind: String, vertices: Vec<usize>, transfers: &[&Transfer]) -> Self {
+        Motif {
+            kind,
+            vertices,
+            hashes: transfers.iter().map(|t| t.hash.clone()).collect(),
+            first_block: transfers.iter().map(|t| t.block).min().unwrap(),
+            last_block: transfers.iter().map(|t| t.block).max().unwrap(),
+            value: transfers.iter().map(|t| t.value).sum(),
+        }
+    }
+}
+
+/// within the relative tolerance of the reference value
+fn similar(value: f64, reference: f64, tolerance: f64) -> bool {
+    (value - reference).abs() <= tolerance * reference.abs()
+}
+
+struct CycleSearch<'a> {
+    transfers: &'a [Transfer],
+    out: Vec<Vec<usize>>,
+    min_len: usize,
+    max_len: usize,
+    tolerance: f64,
+    max_span: Option<u64>,
+    /// the most paths extended from a start, 0 means no limit
+    max_steps: usize,
+    steps: usize,
+    /// the sorted transfers of each found cycle, the same block ones are found from each start
+    seen: HashSet<Vec<usize>>,
+    found: Vec<Motif>,
+}
+
+impl CycleSearch<'_> {
+    /// extend the path in block order, the start is the sender of the first transfer
+    fn search(&mut self, path: &mut Vec<usize>) {
+        self.steps += 1;
+        if self.max_steps > 0 && self.steps > self.max_steps {
+            return;
+        }
+        let transfers = self.transfers;
+        let first = &transfers[path[0]];
+        let last = &transfers[*path.last().unwrap()];
+        let start = first.from;
+
+        if last.to == start {
+            let mut key = path.clone();
+            key.sort_unstable();
+            if path.len() >= self.min_len && self.seen.insert(key) {
+                let chain: Vec<&Transfer> = path.iter().map(|&i| &transfers[i]).collect();
+                let vertices = chain.iter().map(|t| t.from).collect();
+                self.found
+                    .push(Motif::new(format!("cycle{}", path.len()), vertices, &chain));
+            }
+            return;
+        }
+        if path.len() >= self.max_len {
+            return;
+        }
+
+        for k in 0..self.out[last.to].len() {
+            let i = self.out[last.to][k];
+            let t = &transfers[i];
+            if t.block < last.block
+                || !similar(t.value, first.value, self.tolerance)
+                || self
+                    .max_span
+                    .map_or(false, |span| t.block - first.block > span)
+            {
+                continue;
+            }
+            // a simple cycle, only the start may be visited again
+            if t.to != start && path.iter().any(|&j| transfers[j].from == t.to) {
+                continue;
+            }
+
+            path.push(i);
+            self.search(path);
+            path.pop();
+        }
+    }
+}
+
+/// the directed cycles whose transactions are in block order and carry similar values,
+/// the search from each transaction gives up after `max_steps` paths
+pub fn cycles(
+    n: usize,
+    transfers: &[Transfer],
+    min_len: usize,
+    max_len: usize,
+    tolerance: f64,
+    max_span: Option<u64>,
+    max_steps: usize,
+) -> Vec<Motif> {
+    let mut out = vec![Vec::new(); n];
+    for (i, t) in transfers.iter().enumerate() {
+        out[t.from].push(i);
+    }
+
+    let mut search = CycleSearch {
+        transfers,
+        out,
+        min_len: min_len.max(2),
+        max_len,
+        tolerance,
+        max_span,
+        max_steps,
+        steps: 0,
+        seen: HashSet::new(),
+        found: Vec::new(),
+    };
+    let mut truncated = 0;
+    for (i, t) in transfers.iter().enumerate() {
+        // self loops are not cycles
+        if t.from == t.to {
+            continue;
+        }
+        search.steps = 0;
+        search.search(&mut vec![i]);
+        if max_steps > 0 && search.steps > max_steps {
+            truncated += 1;
+        }
+    }
+    if truncated > 0 {
+        log::warn!(
+            "the cycle search gave up on {} transactions after {} steps",
+            truncated,
+            max_steps
+        );
+    }
+    search.found
+}
+
+/// a vertex which sends to (or receives from) at least `fan` distinct counterparties
+/// within `window` blocks, with similar values
+pub fn fans(
+    n: usize,
+    transfers: &[Transfer],
+    fan: usize,
+    window: u64,
+    tolerance: f64,
+) -> Vec<Motif> {
+    let mut found = Vec::new();
+
+    for (kind, outbound) in [("fan_out", true), ("fan_in", false)] {
+        let mut edges: Vec<Vec<&Transfer>> = vec![Vec::new(); n];
+        for t in transfers {
+            let center = if outbound { t.from } else { t.to };
+            edges[center].push(t);
+        }
+
+        for (center, mut edges) in edges.into_iter().enumerate() {
+            if edges.len() < fan {
+                continue;
+            }
+            edges.sort_by_key(|t| t.block);
+
+            let mut i = 0;
+            while i < edges.len() {
+                let anchor = edges[i];
+                let mut counterparties: Vec<usize> = Vec::new();
+                let mut group: Vec<&Transfer> = Vec::new();
+                let mut end = i;
+                for (j, t) in edges.iter().enumerate().skip(i) {
+                    if t.block - anchor.block > window {
+                        break;
+                    }
+                    if !similar(t.value, anchor.value, tolerance) {
+                        continue;
+                    }
+                    let other = if outbound { t.to } else { t.from };
+                    if !counterparties.contains(&other) {
+                        counterparties.push(other);
+                    }
+                    group.push(t);
+                    end = j;
+                }
+
+                if counterparties.len() >= fan {
+                    let mut vertices = vec![center];
+                    vertices.extend(counterparties);
+                    found.push(Motif::new(kind.to_owned(), vertices, &group));
+                    i = end + 1;
+                } else {
+                    i += 1;
+                }
+            }
+        }
+    }
+
+    found
+}
+
+/// the numbered vertices and the transactions among them
+#[derive(Default)]
+struct Transfers {
+    ids: HashMap<Uuid, usize>,
+    uuids: Vec<Uuid>,
+    transfers: Vec<Transfer>,
+}
+
+impl Transfers {
+    fn index(&mut self, id: Uuid) -> usize {
+        if let Some(&i) = self.ids.get(&id) {
+            return i;
+        }
+        self.uuids.push(id);
+        self.ids.insert(id, self.uuids.len() - 1);
+        self.uuids.len() - 1
+    }
+
+    fn push(&mut self, from: Uuid, to: Uuid, hash: &str, block: u64, value: f64) {
+        let from = self.index(from);
+        let to = self.index(to);
+        self.transfers.push(Transfer {
+            from,
+            to,
+            hash: hash.to_owned(),
+            block,
+            value,
+        });
+    }
+}
+
+/// the transfers of the subgraph around the seeds, and the names of its vertices
+fn crawl_transfers<D: Datastore, F: Fn(u64) -> bool>(
+    db: &Database<D>,
+    v: &[String],
+    v_type: VType,
+    hop: usize,
+    stopper: &Stopper,
+    in_blocks: F,
+    transfers: &mut Transfers,
+) -> HashMap<Uuid, String> {
+    let ids: Vec<Uuid> = v
+        .iter()
+        .map(|addr| subgraph::to_uuid(addr, v_type))
+        .collect();
+    let seeds =
+        indradb::util::extract_vertices(db.get(SpecificVertexQuery::new(ids)).unwrap()).unwrap();
+    let sub = subgraph::crawl(db, &seeds, hop, Direction::Both, stopper);
+
+    for (e, tx) in feature::fetch_edge_details(db, sub.edges.clone()) {
+        let block = tx.block_number.unwrap().as_u64();
+        if in_blocks(block) {
+            let value = utils::decimal_to_f64(&utils::wei_to_ether(tx.value));
+            transfers.push(e.outbound_id, e.inbound_id, e.t.as_str(), block, value);
+        }
+    }
+    sub.vertices
+        .iter()
+        .map(|(id, t)| (*id, t.as_str().to_owned()))
+        .collect()
+}
+
+pub fn gen_motifs(
+    path: String,
+    opts: &mut Options,
+    mode: store::OpenMode,
+    v: &mut Vec<String>,
+    v_type: VType,
+    hop: usize,
+    stopper: Stopper,
+    from_block: Option<u64>,
+    to_block: Option<u64>,
+    min_len: usize,
+    max_len: usize,
+    tolerance: f64,
+    max_span: Option<u64>,
+    max_steps: usize,
+    fan: usize,
+    fan_window: u64,
+    output: String,
+) {
+    let in_blocks = |block: u64| {
+        from_block.map_or(true, |from| block >= from) && to_block.map_or(true, |to| block <= to)
+    };
+
+    let mut transfers = Transfers::default();
+    let names: HashMap<Uuid, String> = if v.is_empty() {
+        // no seed, scan the whole block window
+        assert!(
+            from_block.is_some() || to_block.is_some(),
+            "provide the seeds or the block window"
+        );
+        let db = rocks::open_read_only(path, opts);
+        rocks::for_each_edge_property(&db, "details", |out_id, t, in_id, value| {
+            let tx = match codec::decode_or_skip(&value) {
+                Some(tx) => tx,
+                None => return,
+            };
+            let block = match tx.block_number {
+                Some(block) => block.as_u64(),
+                None => return,
+            };
+            if in_blocks(block) {
+                let value = utils::decimal_to_f64(&utils::wei_to_ether(tx.value));
+                transfers.push(out_id, in_id, t, block, value);
+            }
+        });
+        transfers
+            .uuids
+            .iter()
+            .map(|id| (*id, rocks::get_vertex(&db, *id).unwrap()))
+            .collect()
+    } else {
+        v.sort();
+        v.dedup();
+        match store::open(path, opts, mode) {
+            Store::Primary(db) => {
+                crawl_transfers(&db, v, v_type, hop, &stopper, in_blocks, &mut transfers)
+            }
+            Store::ReadOnly(db, _) => {
+                crawl_transfers(&db, v, v_type, hop, &stopper, in_blocks, &mut transfers)
+            }
+        }
+    };
+    log::warn!(
+        "{} vertices, {} transactions",
+        transfers.uuids.len(),
+        transfers.transfers.len()
+    );
+
+    let n = transfers.uuids.len();
+    let mut motifs = cycles(
+        n,
+        &transfers.transfers,
+        min_len,
+        max_len,
+        tolerance,
+        max_span,
+        max_steps,
+    );
+    motifs.extend(fans(n, &transfers.transfers, fan, fan_window, tolerance));
+    log::warn!("{} motifs", motifs.len());
+
+    let mut output = csv::Writer::from_path(output).unwrap();
+    output
+        .write_record([
+            "motif",
+            "addresses",
+            "hashes",
+            "first_block",
+            "last_block",
+            "value",
+        ])
+        .unwrap();
+    for m in motifs {
+        let addresses: Vec<&str> = m
+            .vertices
+            .iter()
+            .map(|&i| names[&transfers.uuids[i]].as_str())
+            .collect();
+        output
+            .write_record([
+                m.kind,
+                addresses.join(" "),
+                m.hashes.join(" "),
+                m.first_block.to_string(),
+                m.last_block.to_string(),
+                m.value.to_string(),
+            ])
+            .unwrap();
+    }
+    output.flush().unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transfer(from: usize, to: usize, block: u64, value: f64) -> Transfer {
+        Transfer {
+            from,
+            to,
+            hash: format!("{}-{}-{}", from, to, block),
+            block,
+            value,
+        }
+    }
+
+    #[test]
+    fn test_cycles() {
+        let transfers = vec![
+            // a round trip 0 -> 1 -> 2 -> 0
+            transfer(0, 1, 10, 1.0),
+            transfer(1, 2, 11, 0.98),
+            transfer(2, 0, 12, 0.97),
+            // starts from the larger vertex: 4 -> 3 -> 4
+            transfer(4, 3, 5, 1.0),
+            transfer(3, 4, 6, 1.0),
+            // different value: 5 <-> 6
+            transfer(5, 6, 1, 1.0),
+            transfer(6, 5, 2, 5.0),
+            // in the same block, found from both transfers: 7 <-> 8
+            transfer(7, 8, 20, 1.0),
+            transfer(8, 7, 20, 1.0),
+        ];
+        let found = cycles(9, &transfers, 2, 5, 0.05, None, 0);
+        assert_eq!(found.len(), 3);
+        assert_eq!(found[0].kind, "cycle3");
+        assert_eq!(found[0].vertices, vec![0, 1, 2]);
+        assert_eq!((found[0].first_block, found[0].last_block), (10, 12));
+        assert_eq!(found[1].kind, "cycle2");
+        assert_eq!(found[1].vertices, vec![4, 3]);
+        assert_eq!(found[2].vertices, vec![7, 8]);
+
+        // 5 <-> 6 differs in value
+        assert_eq!(cycles(9, &transfers, 2, 2, 0.05, None, 0).len(), 2);
+        let within_span = cycles(9, &transfers, 2, 5, 0.05, Some(1), 0);
+        assert!(within_span.iter().all(|m| m.kind == "cycle2"));
+        // the round trip needs 3 steps from its first transfer
+        let capped = cycles(9, &transfers, 2, 5, 0.05, None, 2);
+        assert!(capped.iter().all(|m| m.kind == "cycle2"));
+    }
+
+    #[test]
+    fn test_fans() {
+        let mut transfers: Vec<Transfer> = (1..=5)
+            .map(|i| transfer(0, i, 100 + i as u64, 1.0))
+            .collect();
+        transfers.push(transfer(0, 6, 100, 9.0));
+        transfers.push(transfer(0, 7, 500, 1.0));
+
+        let found = fans(8, &transfers, 5, 10, 0.1);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].kind, "fan_out");
+        assert_eq!(found[0].vertices, vec![0, 1, 2, 3, 4, 5]);
+        assert!(fans(8, &transfers, 6, 10, 0.1).is_empty());
+    }
+}
@@ -0,0 +1,348 @@
+use ethers::types::U256;
+use hashbrown::{HashMap, HashSet};
+use indradb::{Database, Datastore, Edge, PipeQuery, QueryExt, SpecificVertexQuery};
+use rocksdb::Options;
+use uuid::Uuid;
+
+use crate::{
+    feature,
+    store::{self, Store},
+    subgraph::{self, VType},
+    utils,
+};
+
+/// an outbound transaction of the current address
+#[derive(Debug, Clone)]
+pub struct Out {
+    pub to: Uuid,
+    pub block: u64,
+    pub value: U256,
+}
+
+/// an inbound transaction of the current address, only its block and value matter
+#[derive(Debug, Clone)]
+pub struct In {
+    pub block: u64,
+    pub value: U256,
+}
+
+#[derive(Debug, Default)]
+pub struct Chain {
+    /// from the seed to the end
+    pub path: Vec<Uuid>,
+    pub first_block: u64,
+    pub last_block: u64,
+    /// the value forwarded on the first hop
+    pub start_value: U256,
+    /// the value forwarded on the last hop
+    pub end_value: U256,
+    /// the value sent to the other addresses on the way, between the arrival and the forward
+    pub peeled: U256,
+}
+
+impl Chain {
+    /// the hop count
+    pub fn len(&self) -> usize {
+        self.path.len() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn ratio(part: U256, total: U256) -> f64 {
+    utils::decimal_to_f64(&utils::wei_to_ether(part))
+        / utils::decimal_to_f64(&utils::wei_to_ether(total))
+}
+
+/// follow the dominant outbound transaction of each address, among the ones sent after the funds
+/// arrived and before the next inbound funds, stop when it forwards less than `min_ratio`
+/// of what the address received on the hop.
+/// The seed starts from its first inbound transaction since `from_block`,
+/// or from `from_block` against the total it sends when it received nothing since.
+pub fn follow<F, G>(
+    seed: Uuid,
+    from_block: u64,
+    min_ratio: f64,
+    max_len: usize,
+    mut outbound: F,
+    mut inbound: G,
+) -> Chain
+where
+    F: FnMut(Uuid) -> Vec<Out>,
+    G: FnMut(Uuid) -> Vec<In>,
+{
+    let mut chain = Chain {
+        path: vec![seed],
+        ..Default::default()
+    };
+    let mut visited: HashSet<Uuid> = HashSet::from([seed]);
+    let mut cur = seed;
+    let mut ins = inbound(cur);
+    let (mut arrival, mut received) = match ins
+        .iter()
+        .filter(|i| i.block >= from_block)
+        .min_by_key(|i| (i.block, std::cmp::Reverse(i.value)))
+    {
+        Some(first) => (first.block, Some(first.value)),
+        None => (from_block, None),
+    };
+
+    while chain.len() < max_len {
+        // the txs from the next inbound funds on may spend those instead
+        let next_inbound = ins
+            .iter()
+            .map(|i| i.block)
+            .filter(|&block| block > arrival)
+            .min()
+            .unwrap_or(u64::MAX);
+        let outs: Vec<Out> = outbound(cur)
+            .into_iter()
+            .filter(|o| o.block >= arrival && o.block < next_inbound && o.to != cur)
+            .collect();
+        let main = match outs
+            .iter()
+            .max_by_key(|o| (o.value, std::cmp::Reverse(o.block)))
+        {
+            None => break,
+            Some(main) => main.clone(),
+        };
+
+        let base =
+            received.unwrap_or_else(|| outs.iter().fold(U256::zero(), |acc, o| acc + o.value));
+        if base.is_zero() || ratio(main.value, base) < min_ratio || visited.contains(&main.to) {
+            break;
+        }
+
+        if chain.is_empty() {
+            chain.first_block = main.block;
+            chain.start_value = main.value;
+        }
+        chain.last_block = main.block;
+        chain.end_value = main.value;
+        // only what is sent before the funds move on, the later txs spend other funds
+        let peeled = outs
+            .iter()
+            .filter(|o| o.block <= main.block)
+            .fold(U256::zero(), |acc, o| acc + o.value);
+        chain.peeled += peeled - main.value;
+
+        visited.insert(main.to);
+        chain.path.push(main.to);
+        cur = main.to;
+        ins = inbound(cur);
+        arrival = main.block;
+        received = Some(main.value);
+    }
+
+    chain
+}
+
+/// the edges which have details, with their block and value
+fn transfers<D: Datastore>(db: &Database<D>, q: PipeQuery) -> Vec<(Edge, u64, U256)> {
+    let edges = indradb::util::extract_edges(db.get(q).unwrap()).unwrap();
+    feature::fetch_edge_details(db, edges)
+        .into_iter()
+        .filter_map(|(e, tx)| Some((e, tx.block_number?.as_u64(), tx.value)))
+        .collect()
+}
+
+fn outbound<D: Datastore>(db: &Database<D>, id: Uuid) -> Vec<Out> {
+    let q = SpecificVertexQuery::single(id).outbound().unwrap();
+    transfers(db, q)
+        .into_iter()
+        .map(|(e, block, value)| Out {
+            to: e.inbound_id,
+            block,
+            value,
+        })
+        .collect()
+}
+
+fn inbound<D: Datastore>(db: &Database<D>, id: Uuid) -> Vec<In> {
+    let q = SpecificVertexQuery::single(id).inbound().unwrap();
+    transfers(db, q)
+        .into_iter()
+        .map(|(_, block, value)| In { block, value })
+        .collect()
+}
+
+pub fn gen_peel_chains(
+    path: String,
+    opts: &mut Options,
+    mode: store::OpenMode,
+    v: &mut Vec<String>,
+    v_type: VType,
+    from_block: Option<u64>,
+    min_ratio: f64,
+    min_len: usize,
+    max_len: usize,
+    output: String,
+) {
+    let from_block = from_block.unwrap_or(0);
+    match store::open(path, opts, mode) {
+        Store::Primary(db) => write_chains(
+            &db, v, v_type, from_block, min_ratio, min_len, max_len, output,
+        ),
+        Store::ReadOnly(db, _) => write_chains(
+            &db, v, v_type, from_block, min_ratio, min_len, max_len, output,
+        ),
+    }
+}
+
+fn write_chains<D: Datastore>(
+    db: &Database<D>,
+    v: &mut Vec<String>,
+    v_type: VType,
+    from_block: u64,
+    min_ratio: f64,
+    min_len: usize,
+    max_len: usize,
+    output: String,
+) {
+    v.sort();
+    v.dedup();
+
+    let mut output = csv::Writer::from_path(output).unwrap();
+    output
+        .write_record([
+            "seed",
+            "length",
+            "end",
+            "first_block",
+            "last_block",
+            "start_value",
+            "end_value",
+            "peeled",
+            "path",
+        ])
+        .unwrap();
+
+    for addr in v.iter() {
+        let seed = subgraph::to_uuid(addr, v_type);
+        let chain = follow(
+            seed,
+            from_block,
+            min_ratio,
+            max_len,
+            |id| outbound(db, id),
+            |id| inbound(db, id),
+        );
+        log::debug!("{}: {} hops", addr, chain.len());
+        if chain.len() < min_len {
+            continue;
+        }
+
+        let q = SpecificVertexQuery::new(chain.path.clone());
+        let names: HashMap<Uuid, String> = indradb::util::extract_vertices(db.get(q).unwrap())
+            .unwrap()
+            .into_iter()
+            .map(|v| (v.id, v.t.as_str().to_owned()))
+            .collect();
+        let path: Vec<&str> = chain.path.iter().map(|id| names[id].as_str()).collect();
+
+        output
+            .write_record([
+                addr.to_owned(),
+                chain.len().to_string(),
+                path.last().unwrap().to_string(),
+                chain.first_block.to_string(),
+                chain.last_block.to_string(),
+                utils::wei_to_ether(chain.start_value).to_string(),
+                utils::wei_to_ether(chain.end_value).to_string(),
+                utils::wei_to_ether(chain.peeled).to_string(),
+                path.join(" "),
+            ])
+            .unwrap();
+    }
+    output.flush().unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_follow() {
+        let ids: Vec<Uuid> = (0..6u8).map(|i| Uuid::from_bytes([i; 16])).collect();
+        let ether = |v: u64| U256::from(v) * U256::exp10(16); // 0.01 ether
+        let out = |to: usize, block: u64, value: u64| Out {
+            to: ids[to],
+            block,
+            value: ether(value),
+        };
+
+        let mut graph: HashMap<Uuid, Vec<Out>> = HashMap::new();
+        // 0 -> 1 -> 2 -> 3, each peels a little to 5
+        graph.insert(ids[0], vec![out(1, 10, 100), out(5, 10, 5)]);
+        graph.insert(
+            ids[1],
+            vec![
+                out(2, 12, 90),
+                out(5, 11, 8),
+                out(4, 5, 1000),
+                out(5, 20, 2),
+            ],
+        );
+        graph.insert(ids[2], vec![out(3, 13, 80), out(5, 13, 10)]);
+        // 3 splits evenly, the chain ends here
+        graph.insert(ids[3], vec![out(4, 14, 40), out(5, 14, 40)]);
+
+        let outbound =
+            |graph: &HashMap<Uuid, Vec<Out>>, id| graph.get(&id).cloned().unwrap_or_default();
+        let inbound = |graph: &HashMap<Uuid, Vec<Out>>, id| {
+            graph
+                .values()
+                .flatten()
+                .filter(|o| o.to == id)
+                .map(|o| In {
+                    block: o.block,
+                    value: o.value,
+                })
+                .collect()
+        };
+
+        let chain = follow(
+            ids[0],
+            0,
+            0.8,
+            10,
+            |id| outbound(&graph, id),
+            |id| inbound(&graph, id),
+        );
+        assert_eq!(chain.path, ids[..4].to_vec());
+        assert_eq!(chain.len(), 3);
+        assert_eq!((chain.first_block, chain.last_block), (10, 13));
+        assert_eq!(chain.start_value, ether(100));
+        assert_eq!(chain.end_value, ether(80));
+        // the early 1000 from 1 is before the funds arrived and the 2 after they moved on
+        assert_eq!(chain.peeled, ether(5 + 8 + 10));
+
+        let chain = follow(
+            ids[0],
+            0,
+            0.8,
+            2,
+            |id| outbound(&graph, id),
+            |id| inbound(&graph, id),
+        );
+        assert_eq!(chain.len(), 2);
+
+        // 1 forwards a tenth of the 100 it received, the 900 it sends after new funds arrived is
+        // not the same money
+        let mut graph: HashMap<Uuid, Vec<Out>> = HashMap::new();
+        graph.insert(ids[0], vec![out(1, 10, 100)]);
+        graph.insert(ids[1], vec![out(2, 12, 10), out(3, 15, 900)]);
+        graph.insert(ids[4], vec![out(1, 14, 1000)]);
+        let chain = follow(
+            ids[0],
+            0,
+            0.8,
+            10,
+            |id| outbound(&graph, id),
+            |id| inbound(&graph, id),
+        );
+        assert_eq!(chain.path, ids[..2].to_vec());
+    }
+}
@@ -0,0 +1,174 @@
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use rocksdb::Options;
+
+use crate::{
+    graph::{self, Graph},
+    subgraph::VType,
+};
+
+/// power iteration, the random jumps go to the seeds only when seeds are given,
+/// the current and the next rank and the out-degrees are in memory, 20 bytes per vertex
+fn pagerank(
+    g: &dyn Graph,
+    seeds: &[usize],
+    damping: f64,
+    iterations: usize,
+    tolerance: f64,
+) -> Vec<f64> {
+    let n = g.len();
+    let mut seeds = seeds.to_vec();
+    seeds.sort();
+    seeds.dedup();
+
+    let teleport = |i: usize| -> f64 {
+        if seeds.is_empty() {
+            1. / n as f64
+        } else if seeds.binary_search(&i).is_ok() {
+            1. / seeds.len() as f64
+        } else {
+            0.
+        }
+    };
+
+    let out = g.out_degrees();
+    let mut rank: Vec<f64> = (0..n).map(teleport).collect();
+
+    for iteration in 0..iterations {
+        let dangling: f64 = (0..n).filter(|&i| out[i] == 0).map(|i| rank[i]).sum();
+
+        let mut next = vec![0.; n];
+        g.for_each_edge(&mut |from, to| next[to] += rank[from] / out[from] as f64);
+
+        let mut diff = 0.;
+        for (i, r) in next.iter_mut().enumerate() {
+            *r = damping * (*r + dangling * teleport(i)) + (1. - damping) * teleport(i);
+            diff += (*r - rank[i]).abs();
+        }
+        rank = next;
+
+        log::warn!("iteration #{}: diff {}", iteration, diff);
+        if diff < tolerance {
+            break;
+        }
+    }
+
+    rank
+}
+
+/// ordered by the rank only, for the top-n heap
+struct Ranked(f64, usize);
+
+impl PartialEq for Ranked {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Ranked {}
+
+impl PartialOrd for Ranked {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Ranked {
+    /// reversed, so the heap top is the lowest rank
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.0.total_cmp(&self.0)
+    }
+}
+
+fn top_n(rank: &[f64], n: usize) -> Vec<(usize, f64)> {
+    let mut heap = BinaryHeap::with_capacity(n + 1);
+    for (i, &r) in rank.iter().enumerate() {
+        heap.push(Ranked(r, i));
+        if heap.len() > n {
+            heap.pop();
+        }
+    }
+    heap.into_sorted_vec()
+        .into_iter()
+        .map(|Ranked(r, i)| (i, r))
+        .collect()
+}
+
+pub fn gen_rank(
+    path: String,
+    opts: &Options,
+    v: &mut Vec<String>,
+    v_type: VType,
+    edgelist: Option<String>,
+    index: String,
+    output: String,
+    top: usize,
+    damping: f64,
+    iterations: usize,
+    tolerance: f64,
+) {
+    let g = graph::open(path, opts, edgelist, &index, None, None);
+
+    let seeds: Vec<usize> = v
+        .iter()
+        .filter_map(|addr| {
+            let i = g.find(addr, v_type);
+            if i.is_none() {
+                log::warn!("{} not found", addr);
+            }
+            i
+        })
+        .collect();
+    if !seeds.is_empty() {
+        log::warn!("personalised from {} seeds", seeds.len());
+    }
+
+    let rank = pagerank(g.as_ref(), &seeds, damping, iterations, tolerance);
+
+    let mut output = csv::Writer::from_path(output).unwrap();
+    output.write_record(["address", "rank"]).unwrap();
+    for (i, r) in top_n(&rank, top) {
+        output.write_record([g.name(i), r.to_string()]).unwrap();
+    }
+    output.flush().unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::MemGraph;
+
+    fn graph(edges: &[(&str, &str)]) -> MemGraph {
+        let mut g = MemGraph::default();
+        for (from, to) in edges {
+            g.add_edge(from, to);
+        }
+        g
+    }
+
+    #[test]
+    fn test_pagerank() {
+        let g = graph(&[("a", "b"), ("b", "c"), ("c", "a")]);
+        let rank = pagerank(&g, &[], 0.85, 100, 1e-12);
+        for r in &rank {
+            assert!((r - 1. / 3.).abs() < 1e-9);
+        }
+
+        // everyone points to the hub, and the hub is dangling
+        let g = graph(&[("a", "hub"), ("b", "hub"), ("c", "hub")]);
+        let rank = pagerank(&g, &[], 0.85, 100, 1e-12);
+        assert!((rank.iter().sum::<f64>() - 1.).abs() < 1e-9);
+        assert_eq!(top_n(&rank, 1)[0].0, g.find("hub", VType::String).unwrap());
+    }
+
+    #[test]
+    fn test_personalised_pagerank() {
+        let g = graph(&[("a", "b"), ("b", "a"), ("c", "d"), ("d", "c")]);
+        let a = g.find("a", VType::String).unwrap();
+        let rank = pagerank(&g, &[a], 0.85, 100, 1e-12);
+
+        let c = g.find("c", VType::String).unwrap();
+        assert_eq!(rank[c], 0.);
+        assert!(rank[a] > 0.5);
+    }
+}
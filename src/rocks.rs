@@ -0,0 +1,225 @@
+use std::path::Path;
+
+use rocksdb::{Direction, IteratorMode, Options, DB};
+use uuid::Uuid;
+
+/// all column families of the indradb rocksdb datastore
+pub const CF_NAMES: [&str; 8] = [
+    "vertices:v2",
+    "edge_ranges:v2",
+    "reversed_edge_ranges:v2",
+    "vertex_properties:v2",
+    "edge_properties:v2",
+    "vertex_property_values:v2",
+    "edge_property_values:v2",
+    "metadata:v2",
+];
+
+/// open the datastore with rocksdb directly, without taking the write lock
+pub fn open_read_only<P: AsRef<Path>>(path: P, opts: &Options) -> DB {
+    DB::open_cf_for_read_only(opts, path, CF_NAMES, false).unwrap()
+}
+
+/// the uuid at the start of the key
+pub fn read_uuid(key: &[u8]) -> (Uuid, &[u8]) {
+    let id = Uuid::from_slice(&key[..16]).unwrap();
+    (id, &key[16..])
+}
+
+/// the identifier is prefixed with its length in a single byte
+pub fn read_identifier(key: &[u8]) -> (&str, &[u8]) {
+    let len = key[0] as usize;
+    let t = std::str::from_utf8(&key[1..1 + len]).unwrap();
+    (t, &key[1 + len..])
+}
+
+/// `edge_ranges:v2` key: outbound id, type, inbound id,
+/// `reversed_edge_ranges:v2` key: inbound id, type, outbound id
+pub fn decode_edge_range_key(key: &[u8]) -> (Uuid, &str, Uuid) {
+    let (first, rest) = read_uuid(key);
+    let (t, rest) = read_identifier(rest);
+    let (second, _) = read_uuid(rest);
+    (first, t, second)
+}
+
+/// `edge_properties:v2` key: outbound id, type, inbound id, property name
+pub fn decode_edge_property_key(key: &[u8]) -> (Uuid, &str, Uuid, &str) {
+    let (out_id, rest) = read_uuid(key);
+    let (t, rest) = read_identifier(rest);
+    let (in_id, rest) = read_uuid(rest);
+    let (name, _) = read_identifier(rest);
+    (out_id, t, in_id, name)
+}
+
+/// `vertex_properties:v2` key: vertex id, property name
+pub fn decode_vertex_property_key(key: &[u8]) -> (Uuid, &str) {
+    let (id, rest) = read_uuid(key);
+    let (name, _) = read_identifier(rest);
+    (id, name)
+}
+
+/// `vertex_property_values:v2` key: property name, hash of the value, vertex id
+pub fn decode_vertex_property_value_key(key: &[u8]) -> (&str, u64, Uuid) {
+    let (name, rest) = read_identifier(key);
+    let hash = u64::from_be_bytes(rest[..8].try_into().unwrap());
+    let (id, _) = read_uuid(&rest[8..]);
+    (name, hash, id)
+}
+
+/// `edge_property_values:v2` key: property name, hash of the value, outbound id, type, inbound id
+pub fn decode_edge_property_value_key(key: &[u8]) -> (&str, u64, Uuid, &str, Uuid) {
+    let (name, rest) = read_identifier(key);
+    let hash = u64::from_be_bytes(rest[..8].try_into().unwrap());
+    let (out_id, t, in_id) = decode_edge_range_key(&rest[8..]);
+    (name, hash, out_id, t, in_id)
+}
+
+/// `vertices:v2` value: the length prefixed vertex type
+pub fn decode_vertex_value(value: &[u8]) -> &str {
+    read_identifier(value).0
+}
+
+/// calls f(first id, type, second id) on each key of the edge range column family
+pub fn for_each_edge_range<F: FnMut(Uuid, &str, Uuid)>(db: &DB, cf_name: &str, mut f: F) {
+    let iter = db.iterator_cf(db.cf_handle(cf_name).unwrap(), IteratorMode::Start);
+    for row in iter {
+        let (k, _) = row.unwrap();
+        let (first, t, second) = decode_edge_range_key(&k);
+        f(first, t, second);
+    }
+}
+
+/// the count of the keys starting with the prefix
+pub fn count_prefix(db: &DB, cf_name: &str, prefix: &[u8]) -> u64 {
+    let iter = db.iterator_cf(
+        db.cf_handle(cf_name).unwrap(),
+        IteratorMode::From(prefix, Direction::Forward),
+    );
+    let mut count = 0;
+    for row in iter {
+        let (k, _) = row.unwrap();
+        if !k.starts_with(prefix) {
+            break;
+        }
+        count += 1;
+    }
+    count
+}
+
+/// the edge count of the vertex, i.e. the out-degree on `edge_ranges:v2`
+/// and the in-degree on `reversed_edge_ranges:v2`
+pub fn count_edge_ranges(db: &DB, cf_name: &str, id: Uuid) -> u64 {
+    count_prefix(db, cf_name, id.as_bytes())
+}
+
+/// the identifier with its length prefix, as in the keys
+pub fn encode_identifier(t: &str) -> Vec<u8> {
+    let mut key = vec![t.len() as u8];
+    key.extend(t.as_bytes());
+    key
+}
+
+/// calls f(outbound id, type, inbound id, value) on each edge property with the name
+pub fn for_each_edge_property<F: FnMut(Uuid, &str, Uuid, serde_json::Value)>(
+    db: &DB,
+    name: &str,
+    mut f: F,
+) {
+    let iter = db.iterator_cf(
+        db.cf_handle("edge_properties:v2").unwrap(),
+        IteratorMode::Start,
+    );
+    for row in iter {
+        let (k, v) = row.unwrap();
+        let (out_id, t, in_id, prop) = decode_edge_property_key(&k);
+        if prop != name {
+            continue;
+        }
+        f(out_id, t, in_id, serde_json::from_slice(&v).unwrap());
+    }
+}
+
+/// all properties of the vertex
+pub fn vertex_properties(db: &DB, id: Uuid) -> Vec<(String, serde_json::Value)> {
+    let prefix = id.as_bytes();
+    let iter = db.iterator_cf(
+        db.cf_handle("vertex_properties:v2").unwrap(),
+        IteratorMode::From(prefix, Direction::Forward),
+    );
+    let mut properties = Vec::new();
+    for row in iter {
+        let (k, v) = row.unwrap();
+        if !k.starts_with(prefix) {
+            break;
+        }
+        let (_, name) = decode_vertex_property_key(&k);
+        properties.push((name.to_owned(), serde_json::from_slice(&v).unwrap()));
+    }
+    properties
+}
+
+/// the vertex type, i.e. the address
+pub fn get_vertex(db: &DB, id: Uuid) -> Option<String> {
+    db.get_cf(db.cf_handle("vertices:v2").unwrap(), id.as_bytes())
+        .unwrap()
+        .map(|value| decode_vertex_value(&value).to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_edge_range_key() {
+        let out_id = Uuid::from_bytes([1; 16]);
+        let in_id = Uuid::from_bytes([2; 16]);
+        let t = "deadbeef";
+
+        let mut key = out_id.as_bytes().to_vec();
+        key.push(t.len() as u8);
+        key.extend(t.as_bytes());
+        key.extend(in_id.as_bytes());
+
+        assert_eq!(decode_edge_range_key(&key), (out_id, t, in_id));
+
+        key.push(7);
+        key.extend("details".as_bytes());
+        assert_eq!(
+            decode_edge_property_key(&key),
+            (out_id, t, in_id, "details")
+        );
+    }
+
+    #[test]
+    fn test_decode_property_value_key() {
+        let out_id = Uuid::from_bytes([1; 16]);
+        let in_id = Uuid::from_bytes([2; 16]);
+
+        let mut key = vec![12];
+        key.extend("block_number".as_bytes());
+        key.extend(42u64.to_be_bytes());
+        key.extend(out_id.as_bytes());
+        assert_eq!(
+            decode_vertex_property_value_key(&key),
+            ("block_number", 42, out_id)
+        );
+
+        key.push(2);
+        key.extend("ab".as_bytes());
+        key.extend(in_id.as_bytes());
+        // the edge key continues after the outbound id
+        assert_eq!(
+            decode_edge_property_value_key(&key),
+            ("block_number", 42, out_id, "ab", in_id)
+        );
+    }
+
+    #[test]
+    fn test_decode_vertex_value() {
+        assert_eq!(decode_vertex_value(b"\x030x1"), "0x1");
+        assert_eq!(
+            decode_vertex_value(&encode_identifier("chaingraph")),
+            "chaingraph"
+        );
+    }
+}
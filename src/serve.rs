@@ -0,0 +1,479 @@
+use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+
+use axum::{
+    body::{Bytes, StreamBody},
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use hashbrown::{HashMap, HashSet};
+use indradb::{Database, Datastore, Edge, Identifier, QueryExt, SpecificVertexQuery, Vertex};
+use rocksdb::Options;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::{
+    cluster, degree,
+    eth_common::TransactionInfo,
+    feature::{self, FeatureExtracter},
+    store::{self, OpenMode, Store},
+    subgraph::{self, Direction, Stopper, VType},
+};
+
+/// the edges fetched with details at once, and the lines buffered for a slow client
+const BATCH_SIZE: usize = 1_000;
+const STREAM_BUFFER: usize = 1_024;
+
+/// the datastore shared by the request handlers, indradb's own or the read-only one
+pub(crate) trait Shared: Datastore + Send + Sync + 'static {}
+
+impl<D: Datastore + Send + Sync + 'static> Shared for D {}
+
+pub(crate) type Db<D> = Arc<Database<D>>;
+
+fn default_v_type() -> VType {
+    VType::ETHAddress
+}
+
+fn default_direction() -> Direction {
+    Direction::Both
+}
+
+fn default_hop() -> usize {
+    1
+}
+
+#[derive(Deserialize)]
+struct VertexParams {
+    #[serde(default = "default_v_type")]
+    v_type: VType,
+}
+
+#[derive(Deserialize)]
+struct EdgesParams {
+    #[serde(default = "default_v_type")]
+    v_type: VType,
+    #[serde(default = "default_direction")]
+    direction: Direction,
+    /// also return the `details` of each edge
+    #[serde(default)]
+    details: bool,
+}
+
+#[derive(Deserialize)]
+struct DegreeParams {
+    #[serde(default = "default_v_type")]
+    v_type: VType,
+    from_block: Option<u64>,
+    to_block: Option<u64>,
+}
+
+/// the options of the `subgraph` command
+#[derive(Deserialize)]
+pub(crate) struct SubgraphRequest {
+    pub(crate) vertices: Vec<String>,
+    #[serde(default = "default_hop")]
+    pub(crate) hop: usize,
+    #[serde(default = "default_v_type")]
+    pub(crate) v_type: VType,
+    #[serde(default = "default_direction")]
+    pub(crate) direction: Direction,
+    /// the addresses never expanded
+    #[serde(default)]
+    pub(crate) stop_list: Vec<String>,
+    #[serde(default)]
+    pub(crate) max_degree: u64,
+    #[serde(default)]
+    pub(crate) with_props: bool,
+    #[serde(default)]
+    pub(crate) collapse: bool,
+}
+
+/// the options of the `feature` command
+#[derive(Deserialize)]
+pub(crate) struct FeatureRequest {
+    pub(crate) vertices: Vec<String>,
+    #[serde(default)]
+    pub(crate) features: Vec<String>,
+    #[serde(default)]
+    pub(crate) exact: bool,
+    #[serde(default)]
+    pub(crate) neighbourhood: bool,
+    #[serde(default)]
+    pub(crate) stop_list: Vec<String>,
+    #[serde(default)]
+    pub(crate) max_degree: u64,
+    #[serde(default)]
+    pub(crate) collapse: bool,
+}
+
+#[derive(Serialize)]
+pub(crate) struct EdgeLine<'a> {
+    pub(crate) from: &'a str,
+    pub(crate) to: &'a str,
+    pub(crate) hash: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) details: Option<TransactionInfo>,
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+/// the first malformed address, which `to_uuid` would panic on
+pub(crate) fn invalid_address<'a, I: IntoIterator<Item = &'a String>>(
+    addrs: I,
+    v_type: VType,
+) -> Option<&'a String> {
+    addrs
+        .into_iter()
+        .find(|addr| subgraph::try_to_uuid(addr, v_type).is_none())
+}
+
+fn bad_address(addr: &str) -> Response {
+    error(
+        StatusCode::BAD_REQUEST,
+        &format!("invalid address: {}", addr),
+    )
+}
+
+fn find<D: Datastore>(db: &Database<D>, addr: &str, v_type: VType) -> Option<Vertex> {
+    let q = SpecificVertexQuery::single(subgraph::to_uuid(addr, v_type));
+    indradb::util::extract_vertices(db.get(q).unwrap())
+        .unwrap()
+        .pop()
+}
+
+fn find_all<D: Datastore>(db: &Database<D>, addrs: &[String], v_type: VType) -> Vec<Vertex> {
+    let ids = addrs
+        .iter()
+        .map(|addr| subgraph::to_uuid(addr, v_type))
+        .collect();
+    indradb::util::extract_vertices(db.get(SpecificVertexQuery::new(ids)).unwrap()).unwrap()
+}
+
+/// false when the client has gone, so the job can stop early
+fn send_line<T: Serialize>(tx: &mpsc::Sender<Bytes>, value: &T) -> bool {
+    let mut line = serde_json::to_vec(value).unwrap();
+    line.push(b'\n');
+    tx.blocking_send(Bytes::from(line)).is_ok()
+}
+
+/// run the job on a blocking thread and stream what it sends as ndjson
+fn stream<F>(job: F) -> Response
+where
+    F: FnOnce(mpsc::Sender<Bytes>) + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel::<Bytes>(STREAM_BUFFER);
+    tokio::task::spawn_blocking(move || job(tx));
+
+    let body = futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|line| (Ok::<_, Infallible>(line), rx))
+    });
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        StreamBody::new(body),
+    )
+        .into_response()
+}
+
+/// send the edges in batches, the addresses are resolved by `name`
+fn send_edges<D, N, S>(db: &Database<D>, edges: &[Edge], details: bool, name: N, mut send: S)
+where
+    D: Datastore,
+    N: Fn(&Edge) -> (String, String),
+    S: FnMut(EdgeLine) -> bool,
+{
+    for chunk in edges.chunks(BATCH_SIZE) {
+        let lines: Vec<(Edge, Option<TransactionInfo>)> = if details {
+            feature::fetch_edge_details(db, chunk.to_vec())
+                .into_iter()
+                .map(|(e, tx)| (e, Some(tx)))
+                .collect()
+        } else {
+            chunk.iter().map(|e| (e.clone(), None)).collect()
+        };
+
+        for (e, details) in lines {
+            let (from, to) = name(&e);
+            let line = EdgeLine {
+                from: &from,
+                to: &to,
+                hash: e.t.as_str(),
+                details,
+            };
+            if !send(line) {
+                return;
+            }
+        }
+    }
+}
+
+async fn get_vertex<D: Shared>(
+    State(db): State<Db<D>>,
+    Path(addr): Path<String>,
+    Query(params): Query<VertexParams>,
+) -> Response {
+    if let Some(addr) = invalid_address([&addr], params.v_type) {
+        return bad_address(addr);
+    }
+    let v = tokio::task::spawn_blocking(move || find(&db, &addr, params.v_type))
+        .await
+        .unwrap();
+    match v {
+        None => error(StatusCode::NOT_FOUND, "vertex not found"),
+        Some(v) => Json(json!({ "id": v.id, "address": v.t.as_str() })).into_response(),
+    }
+}
+
+async fn get_edges<D: Shared>(
+    State(db): State<Db<D>>,
+    Path(addr): Path<String>,
+    Query(params): Query<EdgesParams>,
+) -> Response {
+    if let Some(addr) = invalid_address([&addr], params.v_type) {
+        return bad_address(addr);
+    }
+    let v = {
+        let db = db.clone();
+        tokio::task::spawn_blocking(move || find(&db, &addr, params.v_type))
+            .await
+            .unwrap()
+    };
+    let v = match v {
+        None => return error(StatusCode::NOT_FOUND, "vertex not found"),
+        Some(v) => v,
+    };
+
+    stream(move |tx| {
+        let mut edges = Vec::new();
+        if params.direction != Direction::In {
+            let q = SpecificVertexQuery::single(v.id).outbound().unwrap();
+            edges.extend(indradb::util::extract_edges(db.get(q).unwrap()).unwrap());
+        }
+        if params.direction != Direction::Out {
+            let q = SpecificVertexQuery::single(v.id).inbound().unwrap();
+            let inbound = indradb::util::extract_edges(db.get(q).unwrap()).unwrap();
+            // the self loops are already outbound
+            edges.extend(
+                inbound
+                    .into_iter()
+                    .filter(|e| params.direction == Direction::In || e.outbound_id != e.inbound_id),
+            );
+        }
+
+        // the counterparties are only known by id, look each one up
+        let name = |e: &Edge| {
+            let name = |id| {
+                if id == v.id {
+                    v.t.as_str().to_owned()
+                } else {
+                    let q = SpecificVertexQuery::single(id);
+                    indradb::util::extract_vertices(db.get(q).unwrap()).unwrap()[0]
+                        .t
+                        .as_str()
+                        .to_owned()
+                }
+            };
+            (name(e.outbound_id), name(e.inbound_id))
+        };
+        send_edges(&db, &edges, params.details, name, |line| {
+            send_line(&tx, &line)
+        });
+    })
+}
+
+async fn get_degree<D: Shared>(
+    State(db): State<Db<D>>,
+    Path(addr): Path<String>,
+    Query(params): Query<DegreeParams>,
+) -> Response {
+    if let Some(addr) = invalid_address([&addr], params.v_type) {
+        return bad_address(addr);
+    }
+    let d = tokio::task::spawn_blocking(move || {
+        let id = subgraph::to_uuid(&addr, params.v_type);
+        match (params.from_block, params.to_block) {
+            (None, None) => degree::degree(&db, id),
+            (from, to) => {
+                degree::degree_in_blocks(&db, id, from.unwrap_or(0), to.unwrap_or(u64::MAX))
+            }
+        }
+    })
+    .await
+    .unwrap();
+    Json(d).into_response()
+}
+
+/// crawl the subgraph like the `subgraph` command, the edges are sent as they are crawled
+pub(crate) fn run_subgraph<D: Datastore, S: FnMut(EdgeLine) -> bool>(
+    db: &Database<D>,
+    req: &SubgraphRequest,
+    mut send: S,
+) {
+    let stopper = Stopper::from_addrs(&req.stop_list, req.max_degree, req.v_type);
+    let seeds = find_all(db, &req.vertices, req.v_type);
+
+    // the cluster ids of the looked up vertices, None unless collapsing
+    let mut clusters: Option<HashMap<Uuid, String>> = req.collapse.then(HashMap::new);
+    let mut looked_up = HashSet::new();
+    // the crawl can't be interrupted, only the sending stops when the client has gone
+    let mut gone = false;
+    let mut sink = |vertices: &HashMap<Uuid, Identifier>, edges: &[Edge]| {
+        if gone {
+            return;
+        }
+        if let Some(clusters) = &mut clusters {
+            let ids = edges
+                .iter()
+                .flat_map(|e| [e.outbound_id, e.inbound_id])
+                .filter(|id| looked_up.insert(*id))
+                .collect();
+            clusters.extend(cluster::get_clusters(db, ids));
+        }
+        let name = |id: &Uuid| match clusters.as_ref().and_then(|clusters| clusters.get(id)) {
+            Some(cluster) => cluster.to_owned(),
+            None => vertices[id].as_str().to_owned(),
+        };
+        // the edges inside a cluster are dropped
+        let edges: Vec<Edge> = edges
+            .iter()
+            .filter(|e| clusters.is_none() || name(&e.outbound_id) != name(&e.inbound_id))
+            .cloned()
+            .collect();
+        let name = |e: &Edge| (name(&e.outbound_id), name(&e.inbound_id));
+        send_edges(db, &edges, req.with_props, name, |line| {
+            gone = !send(line);
+            !gone
+        });
+    };
+    subgraph::crawl_into(
+        db,
+        &seeds,
+        req.hop,
+        req.direction,
+        &stopper,
+        Some(&mut sink),
+    );
+}
+
+/// extract the features like the `feature` command, each record is paired with the header,
+/// it stops at the first vertex which has no features
+pub(crate) fn run_features<D, S>(db: &Db<D>, req: &FeatureRequest, mut send: S)
+where
+    D: Shared,
+    S: FnMut(Result<Vec<(String, String)>, String>) -> bool,
+{
+    let features = match feature::select_features(&req.features) {
+        Ok(features) => features,
+        Err(e) => {
+            send(Err(e));
+            return;
+        }
+    };
+    let neighbourhood = req
+        .neighbourhood
+        .then(|| Stopper::from_addrs(&req.stop_list, req.max_degree, VType::ETHAddress));
+    let fe = FeatureExtracter::with_db(
+        db.clone(),
+        features,
+        neighbourhood,
+        1,
+        req.exact,
+        req.collapse,
+    );
+    let header = fe.header();
+
+    for v in find_all(db, &req.vertices, VType::ETHAddress) {
+        let record = fe
+            .record(&v)
+            .map(|record| header.iter().cloned().zip(record).collect());
+        let failed = record.is_err();
+        if !send(record) || failed {
+            return;
+        }
+    }
+}
+
+async fn post_subgraph<D: Shared>(
+    State(db): State<Db<D>>,
+    Json(req): Json<SubgraphRequest>,
+) -> Response {
+    if let Some(addr) = invalid_address(req.vertices.iter().chain(&req.stop_list), req.v_type) {
+        return bad_address(addr);
+    }
+    stream(move |tx| run_subgraph(&db, &req, |line| send_line(&tx, &line)))
+}
+
+async fn post_features<D: Shared>(
+    State(db): State<Db<D>>,
+    Json(req): Json<FeatureRequest>,
+) -> Response {
+    if let Some(p) = feature::unknown_feature(&req.features) {
+        return error(StatusCode::BAD_REQUEST, &format!("unknown feature: {}", p));
+    }
+    if let Some(addr) =
+        invalid_address(req.vertices.iter().chain(&req.stop_list), VType::ETHAddress)
+    {
+        return bad_address(addr);
+    }
+
+    stream(move |tx| {
+        run_features(&db, &req, |record| {
+            let record = match record {
+                Ok(record) => record,
+                Err(e) => return send_line(&tx, &json!({ "error": e })),
+            };
+            let record: serde_json::Map<String, serde_json::Value> = record
+                .into_iter()
+                .map(|(name, x)| {
+                    // the exact decimals are kept as strings
+                    let x = match x.parse::<f64>() {
+                        Ok(f) if !req.exact => serde_json::Value::from(f),
+                        _ => serde_json::Value::from(x),
+                    };
+                    (name, x)
+                })
+                .collect();
+            send_line(&tx, &record)
+        })
+    })
+}
+
+pub fn serve(path: String, opts: &mut Options, mode: OpenMode, catch_up: u64, listen: String) {
+    match store::open(path, opts, mode) {
+        Store::Primary(db) => serve_db(Arc::new(db), listen),
+        Store::ReadOnly(db, raw) => {
+            store::keep_catching_up(raw, mode, catch_up);
+            serve_db(Arc::new(db), listen)
+        }
+    }
+}
+
+fn serve_db<D: Shared>(db: Db<D>, listen: String) {
+    let app = Router::new()
+        .route("/vertex/:addr", get(get_vertex::<D>))
+        .route("/edges/:addr", get(get_edges::<D>))
+        .route("/degree/:addr", get(get_degree::<D>))
+        .route("/subgraph", post(post_subgraph::<D>))
+        .route("/features", post(post_features::<D>))
+        .with_state(db);
+
+    let addr: SocketAddr = listen.parse().unwrap();
+    log::warn!("listening on {}", addr);
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async {
+            axum::Server::bind(&addr)
+                .serve(app.into_make_service())
+                .await
+                .unwrap()
+        })
+}
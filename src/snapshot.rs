@@ -0,0 +1,204 @@
+use std::{
+    fs, io,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rocksdb::{
+    backup::{BackupEngine, BackupEngineOptions, RestoreOptions},
+    checkpoint::Checkpoint,
+    Env, Options, DB,
+};
+use serde_json::json;
+
+use crate::{
+    codec, link,
+    rocks::{self, CF_NAMES},
+};
+
+/// the metadata of a checkpoint, written beside the rocksdb files
+const META_FILE: &str = "chaingraph.json";
+
+/// the metadata of each backup in the backup directory
+fn backup_meta_file(backup_id: u32) -> String {
+    format!("chaingraph-{}.json", backup_id)
+}
+
+/// where the copy comes from, the details format and the linked blocks, null unless linked
+fn metadata(db: &DB, path: &str) -> serde_json::Value {
+    json!({
+        "source": path,
+        "created": SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+        "column_families": CF_NAMES,
+        "details_format": codec::read_format(db),
+        "linked_blocks": link::read_linked_blocks(db),
+    })
+}
+
+fn write_metadata(file: &Path, meta: &serde_json::Value) {
+    fs::write(file, serde_json::to_string_pretty(meta).unwrap()).unwrap();
+}
+
+fn backup_engine(dir: &str) -> BackupEngine {
+    let opts = BackupEngineOptions::new(dir).unwrap();
+    BackupEngine::open(&opts, &Env::new().unwrap()).unwrap()
+}
+
+/// a hard linked checkpoint in `output`, or a new incremental backup in the `output` backup directory
+pub fn snapshot(path: String, opts: &Options, output: String, backup: bool, keep: usize) {
+    let db = DB::open_cf(opts, &path, CF_NAMES).unwrap();
+    let mut meta = metadata(&db, &path);
+
+    if !backup {
+        Checkpoint::new(&db)
+            .unwrap()
+            .create_checkpoint(&output)
+            .unwrap();
+        write_metadata(&Path::new(&output).join(META_FILE), &meta);
+        log::warn!("checkpoint at {}", output);
+        return;
+    }
+
+    let mut engine = backup_engine(&output);
+    // flush the memtables first, so the backup doesn't depend on the wal
+    engine.create_new_backup_flush(&db, true).unwrap();
+    let info = engine.get_backup_info();
+    let latest = info.iter().max_by_key(|info| info.backup_id).unwrap();
+    meta["backup_id"] = json!(latest.backup_id);
+    write_metadata(
+        &Path::new(&output).join(backup_meta_file(latest.backup_id)),
+        &meta,
+    );
+    log::warn!(
+        "backup {} in {}: {} files, {} bytes",
+        latest.backup_id,
+        output,
+        latest.num_files,
+        latest.size
+    );
+
+    if keep > 0 {
+        engine.purge_old_backups(keep).unwrap();
+        let kept: Vec<u32> = engine
+            .get_backup_info()
+            .iter()
+            .map(|info| info.backup_id)
+            .collect();
+        for entry in fs::read_dir(&output).unwrap() {
+            let name = entry.unwrap().file_name().to_string_lossy().into_owned();
+            let purged = name
+                .strip_prefix("chaingraph-")
+                .and_then(|name| name.strip_suffix(".json"))
+                .and_then(|id| id.parse::<u32>().ok())
+                .map_or(false, |id| !kept.contains(&id));
+            if purged {
+                fs::remove_file(Path::new(&output).join(name)).unwrap();
+            }
+        }
+    }
+}
+
+/// copy a checkpoint, the table files are hard linked when on the same filesystem
+fn link_files(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name == META_FILE || !entry.file_type()?.is_file() {
+            continue;
+        }
+
+        let (src, dst) = (from.join(&name), to.join(&name));
+        if name.to_string_lossy().ends_with(".sst") && fs::hard_link(&src, &dst).is_ok() {
+            continue;
+        }
+        fs::copy(&src, &dst)?;
+    }
+    Ok(())
+}
+
+fn print_metadata(file: &Path) {
+    match fs::read_to_string(file) {
+        Ok(meta) => println!("{}", meta),
+        Err(_) => log::warn!("no metadata at {}", file.display()),
+    }
+}
+
+/// restore the datastore at `path` from a checkpoint or a backup directory
+pub fn restore(
+    path: String,
+    input: String,
+    backup_id: Option<u32>,
+    list: bool,
+) -> Result<(), String> {
+    let is_checkpoint = Path::new(&input).join("CURRENT").exists();
+
+    if list {
+        if is_checkpoint {
+            print_metadata(&Path::new(&input).join(META_FILE));
+            return Ok(());
+        }
+        for info in backup_engine(&input).get_backup_info() {
+            println!(
+                "{}\t{}\t{} files\t{} bytes",
+                info.backup_id, info.timestamp, info.num_files, info.size
+            );
+        }
+        return Ok(());
+    }
+
+    let empty = fs::read_dir(&path).map_or(true, |mut dir| dir.next().is_none());
+    if !empty {
+        return Err(format!("restore into an empty directory, {} is not", path));
+    }
+
+    if is_checkpoint {
+        if backup_id.is_some() {
+            return Err(format!("{} is a checkpoint, which has no backup id", input));
+        }
+        link_files(Path::new(&input), Path::new(&path)).map_err(|e| e.to_string())?;
+        print_metadata(&Path::new(&input).join(META_FILE));
+    } else {
+        let mut engine = backup_engine(&input);
+        let opts = RestoreOptions::default();
+        let info = engine.get_backup_info();
+        let backup_id = match backup_id {
+            Some(id) if info.iter().any(|info| info.backup_id == id) => id,
+            Some(id) => return Err(format!("no backup {} in {}", id, input)),
+            None => match info.iter().map(|info| info.backup_id).max() {
+                Some(id) => id,
+                None => return Err(format!("no checkpoint or backup in {}", input)),
+            },
+        };
+        engine
+            .restore_from_backup(&path, &path, &opts, backup_id)
+            .map_err(|e| e.to_string())?;
+        print_metadata(&Path::new(&input).join(backup_meta_file(backup_id)));
+    }
+    log::warn!("restored {} from {}", path, input);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_link_files() {
+        let root =
+            std::env::temp_dir().join(format!("chaingraph-test-checkpoint-{}", std::process::id()));
+        let (from, to) = (root.join("checkpoint"), root.join("rocks"));
+        fs::create_dir_all(&from).unwrap();
+        for name in ["CURRENT", "MANIFEST-000005", "000010.sst", META_FILE] {
+            fs::write(from.join(name), name).unwrap();
+        }
+
+        link_files(&from, &to).unwrap();
+        for name in ["CURRENT", "MANIFEST-000005", "000010.sst"] {
+            assert_eq!(fs::read_to_string(to.join(name)).unwrap(), name);
+        }
+        assert!(!to.join(META_FILE).exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
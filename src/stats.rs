@@ -0,0 +1,340 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap},
+    ffi::CStr,
+    fs,
+    iter::Peekable,
+};
+
+use rocksdb::{properties, IteratorMode, Options, DB};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    codec,
+    degree::Degree,
+    rocks::{self, CF_NAMES},
+    store,
+};
+
+/// how often rocksdb writes the statistics into its LOG while loading
+const STATS_DUMP_PERIOD_SEC: u32 = 600;
+
+/// collect the rocksdb statistics, before opening the datastore
+pub fn enable_statistics(opts: &mut Options) {
+    opts.enable_statistics();
+    opts.set_stats_dump_period_sec(STATS_DUMP_PERIOD_SEC);
+}
+
+/// write what was collected since `enable_statistics`
+pub fn save_statistics(opts: &Options, file: &str) {
+    match opts.get_statistics() {
+        Some(statistics) => {
+            fs::write(file, statistics).unwrap();
+            log::warn!("rocksdb statistics saved to {}", file);
+        }
+        None => log::warn!("no rocksdb statistics collected"),
+    }
+}
+
+/// the count of the vertices per power of two degree, bucket k holds [2^k, 2^(k+1))
+#[derive(Default, Debug, PartialEq)]
+struct Histogram {
+    buckets: Vec<u64>,
+}
+
+impl Histogram {
+    fn add(&mut self, degree: u64) {
+        let k = (u64::BITS - degree.leading_zeros()).saturating_sub(1) as usize;
+        if self.buckets.len() <= k {
+            self.buckets.resize(k + 1, 0);
+        }
+        self.buckets[k] += 1;
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        self.buckets
+            .iter()
+            .enumerate()
+            .map(|(k, vertices)| {
+                json!({
+                    "min": 1u64 << k,
+                    "max": (1u64 << k << 1) - 1,
+                    "vertices": vertices,
+                })
+            })
+            .collect()
+    }
+}
+
+/// the n vertices of the highest degree
+struct Hubs {
+    n: usize,
+    heap: BinaryHeap<Reverse<(u64, Uuid, u64, u64)>>,
+}
+
+impl Hubs {
+    fn new(n: usize) -> Self {
+        Hubs {
+            n,
+            heap: BinaryHeap::with_capacity(n + 1),
+        }
+    }
+
+    fn add(&mut self, id: Uuid, d: Degree) {
+        self.heap
+            .push(Reverse((d.degree, id, d.in_degree, d.out_degree)));
+        if self.heap.len() > self.n {
+            self.heap.pop();
+        }
+    }
+
+    /// the highest first
+    fn into_sorted(self) -> Vec<(Uuid, Degree)> {
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((degree, id, in_degree, out_degree))| {
+                let d = Degree {
+                    in_degree,
+                    out_degree,
+                    degree,
+                };
+                (id, d)
+            })
+            .collect()
+    }
+}
+
+/// the next id and how many times it repeats
+fn next_run<I: Iterator<Item = Uuid>>(ids: &mut Peekable<I>) -> Option<(Uuid, u64)> {
+    let id = ids.next()?;
+    let mut count = 1;
+    while ids.next_if_eq(&id).is_some() {
+        count += 1;
+    }
+    Some((id, count))
+}
+
+/// calls f(id, degree) on each vertex with edges, merging the sorted first ids of
+/// `edge_ranges:v2` (the outbound ids) and `reversed_edge_ranges:v2` (the inbound ids)
+fn for_each_degree<O, I, F>(out_ids: O, in_ids: I, mut f: F)
+where
+    O: Iterator<Item = Uuid>,
+    I: Iterator<Item = Uuid>,
+    F: FnMut(Uuid, Degree),
+{
+    let (mut out_ids, mut in_ids) = (out_ids.peekable(), in_ids.peekable());
+    let mut out_run = next_run(&mut out_ids);
+    let mut in_run = next_run(&mut in_ids);
+    loop {
+        let (id, out_degree, in_degree) = match (out_run, in_run) {
+            (None, None) => break,
+            (Some((o, n)), Some((i, _))) if o < i => {
+                out_run = next_run(&mut out_ids);
+                (o, n, 0)
+            }
+            (Some((o, _)), Some((i, m))) if i < o => {
+                in_run = next_run(&mut in_ids);
+                (i, 0, m)
+            }
+            (Some((o, n)), Some((_, m))) => {
+                out_run = next_run(&mut out_ids);
+                in_run = next_run(&mut in_ids);
+                (o, n, m)
+            }
+            (Some((o, n)), None) => {
+                out_run = next_run(&mut out_ids);
+                (o, n, 0)
+            }
+            (None, Some((i, m))) => {
+                in_run = next_run(&mut in_ids);
+                (i, 0, m)
+            }
+        };
+        f(
+            id,
+            Degree {
+                in_degree,
+                out_degree,
+                degree: in_degree + out_degree,
+            },
+        );
+    }
+}
+
+/// the first ids of the keys, without decoding the rest
+fn first_ids<'a>(db: &'a DB, cf_name: &str) -> impl Iterator<Item = Uuid> + 'a {
+    db.iterator_cf(db.cf_handle(cf_name).unwrap(), IteratorMode::Start)
+        .map(|row| rocks::read_uuid(&row.unwrap().0).0)
+}
+
+fn degrees(db: &DB, top: usize) -> serde_json::Value {
+    let mut histogram = Histogram::default();
+    let mut hubs = Hubs::new(top);
+    let mut vertices = 0u64;
+    for_each_degree(
+        first_ids(db, "edge_ranges:v2"),
+        first_ids(db, "reversed_edge_ranges:v2"),
+        |id, d| {
+            histogram.add(d.degree);
+            hubs.add(id, d);
+            vertices += 1;
+        },
+    );
+
+    let top: Vec<serde_json::Value> = hubs
+        .into_sorted()
+        .into_iter()
+        .map(|(id, d)| {
+            json!({
+                "address": rocks::get_vertex(db, id),
+                "in_degree": d.in_degree,
+                "out_degree": d.out_degree,
+                "degree": d.degree,
+            })
+        })
+        .collect();
+    json!({
+        "vertices_with_edges": vertices,
+        "histogram": histogram.to_json(),
+        "top": top,
+    })
+}
+
+/// the block range of the edges with details, the edges loaded from a csv have none
+fn blocks(db: &DB) -> serde_json::Value {
+    let (mut first, mut last, mut edges) = (u64::MAX, 0, 0u64);
+    rocks::for_each_edge_property(db, "details", |_, _, _, value| {
+        if let Some(number) = codec::decode_or_skip(&value).and_then(|tx| tx.block_number) {
+            first = first.min(number.as_u64());
+            last = last.max(number.as_u64());
+            edges += 1;
+        }
+    });
+    if edges == 0 {
+        return serde_json::Value::Null;
+    }
+    json!({"first": first, "last": last, "edges": edges})
+}
+
+/// the sizes of each column family and its table files per level
+fn column_families(db: &DB) -> Vec<serde_json::Value> {
+    // (files, bytes, entries) per level
+    let mut levels: BTreeMap<(String, i32), (u64, u64, u64)> = BTreeMap::new();
+    for file in db.live_files().unwrap() {
+        let level = levels
+            .entry((file.column_family_name, file.level))
+            .or_default();
+        level.0 += 1;
+        level.1 += file.size as u64;
+        level.2 += file.num_entries;
+    }
+
+    CF_NAMES
+        .iter()
+        .map(|name| {
+            let cf = db.cf_handle(name).unwrap();
+            let int = |p: &CStr| db.property_int_value_cf(cf, p).unwrap();
+            let cf_levels: Vec<serde_json::Value> = levels
+                .iter()
+                .filter(|((cf_name, _), _)| cf_name.as_str() == *name)
+                .map(|((_, level), (files, bytes, entries))| {
+                    json!({"level": level, "files": files, "bytes": bytes, "entries": entries})
+                })
+                .collect();
+            json!({
+                "name": name,
+                "estimated_keys": int(properties::ESTIMATE_NUM_KEYS),
+                "sst_bytes": int(properties::TOTAL_SST_FILES_SIZE),
+                "live_data_bytes": int(properties::ESTIMATE_LIVE_DATA_SIZE),
+                "memtable_bytes": int(properties::CUR_SIZE_ALL_MEM_TABLES),
+                "levels": cf_levels,
+            })
+        })
+        .collect()
+}
+
+/// print the datastore statistics as json, the block range and the degrees scan every edge
+pub fn stats(
+    path: String,
+    opts: &Options,
+    mode: store::OpenMode,
+    exact: bool,
+    with_blocks: bool,
+    with_degrees: bool,
+    top: usize,
+) {
+    let db = store::open_raw(path.clone(), opts, mode);
+
+    let count = |cf_name: &str| -> u64 {
+        if exact {
+            rocks::count_prefix(&db, cf_name, &[])
+        } else {
+            let cf = db.cf_handle(cf_name).unwrap();
+            db.property_int_value_cf(cf, properties::ESTIMATE_NUM_KEYS)
+                .unwrap()
+                .unwrap_or(0)
+        }
+    };
+    let mut stats = json!({
+        "path": path,
+        "exact": exact,
+        "vertices": count("vertices:v2"),
+        "edges": count("edge_ranges:v2"),
+        "column_families": column_families(&db),
+    });
+
+    if with_blocks {
+        log::warn!("scanning the details for the block range");
+        stats["blocks"] = blocks(&db);
+    }
+    if with_degrees {
+        log::warn!("scanning the edge ranges for the degrees");
+        stats["degrees"] = degrees(&db, top);
+    }
+
+    println!("{}", serde_json::to_string_pretty(&stats).unwrap());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_for_each_degree() {
+        let id = |n: u8| Uuid::from_bytes([n; 16]);
+        let out_ids = vec![id(1), id(1), id(2), id(4)];
+        let in_ids = vec![id(2), id(3), id(3), id(3), id(4)];
+
+        let mut found = Vec::new();
+        for_each_degree(out_ids.into_iter(), in_ids.into_iter(), |id, d| {
+            found.push((id, d.out_degree, d.in_degree))
+        });
+        assert_eq!(
+            found,
+            vec![(id(1), 2, 0), (id(2), 1, 1), (id(3), 0, 3), (id(4), 1, 1)]
+        );
+    }
+
+    #[test]
+    fn test_histogram_and_hubs() {
+        let mut histogram = Histogram::default();
+        let mut hubs = Hubs::new(2);
+        for (n, degree) in [1u64, 2, 3, 4, 9].into_iter().enumerate() {
+            histogram.add(degree);
+            let d = Degree {
+                in_degree: degree,
+                out_degree: 0,
+                degree,
+            };
+            hubs.add(Uuid::from_bytes([n as u8; 16]), d);
+        }
+        // [1], [2, 3], [4, 7], [8, 15]
+        assert_eq!(histogram.buckets, vec![1, 2, 1, 1]);
+        assert_eq!(histogram.to_json()[3]["max"], 15);
+
+        let degrees: Vec<u64> = hubs.into_sorted().iter().map(|(_, d)| d.degree).collect();
+        assert_eq!(degrees, vec![9, 4]);
+    }
+}
@@ -0,0 +1,405 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use indradb::{
+    BulkInsertItem, Database, Datastore, DynIter, Edge, Identifier, Json, RocksdbDatastore,
+    Transaction, Vertex,
+};
+use rocksdb::{Direction, IteratorMode, Options, DB};
+use uuid::Uuid;
+
+use crate::{
+    index,
+    rocks::{self, CF_NAMES},
+};
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum OpenMode {
+    /// take the write lock, no writer can run at the same time
+    Primary,
+    /// a fixed view of the data at opening
+    ReadOnly,
+    /// a view which catches up with the writer, periodically in `serve` and `grpc`
+    Secondary,
+}
+
+/// the datastore of the query commands
+pub enum Store {
+    Primary(Database<RocksdbDatastore>),
+    /// a read-only or secondary instance, the rocksdb is kept for the catch-up
+    ReadOnly(Database<ReadOnlyDatastore>, Arc<DB>),
+}
+
+/// the secondary instances of `path` keep their own logs in `<path>.secondaries`
+fn secondaries_dir(path: &str) -> PathBuf {
+    PathBuf::from(format!("{}.secondaries", path.trim_end_matches('/')))
+}
+
+/// whether the process is still running, None where it can't be told
+fn is_running(pid: &str) -> Option<bool> {
+    if cfg!(target_os = "linux") {
+        Some(Path::new("/proc").join(pid).exists())
+    } else {
+        None
+    }
+}
+
+/// remove what the finished processes left, the entries are named `<pid>-...`
+fn remove_stale_secondaries(dir: &Path) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let pid = name.split('-').next().unwrap_or_default();
+        if pid != std::process::id().to_string() && is_running(pid) == Some(false) {
+            log::debug!("remove the stale secondary {}", name);
+            fs::remove_dir_all(entry.path()).ok();
+        }
+    }
+}
+
+/// open the datastore for the queries. indradb opens the rocksdb itself and can't wrap a
+/// read-only or secondary instance, so those are read by `ReadOnlyDatastore`
+pub fn open(path: String, opts: &mut Options, mode: OpenMode) -> Store {
+    match mode {
+        OpenMode::Primary => {
+            Store::Primary(RocksdbDatastore::new_db_with_options(path, opts).unwrap())
+        }
+        _ => {
+            let db = Arc::new(open_raw(path, opts, mode));
+            Store::ReadOnly(Database::new(ReadOnlyDatastore { db: db.clone() }), db)
+        }
+    }
+}
+
+/// catch the secondary instance up with the writer every `interval` seconds in the background,
+/// the read-only one stays as it was opened
+pub fn keep_catching_up(db: Arc<DB>, mode: OpenMode, interval: u64) {
+    if mode != OpenMode::Secondary || interval == 0 {
+        return;
+    }
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(interval));
+        match db.try_catch_up_with_primary() {
+            Ok(()) => log::debug!("caught up with the writer"),
+            Err(e) => log::warn!("failed to catch up with the writer: {}", e),
+        }
+    });
+}
+
+/// open rocksdb directly for the raw scans
+pub fn open_raw(path: String, opts: &Options, mode: OpenMode) -> DB {
+    match mode {
+        OpenMode::Primary => DB::open_cf(opts, path, CF_NAMES).unwrap(),
+        OpenMode::ReadOnly => rocks::open_read_only(path, opts),
+        OpenMode::Secondary => {
+            let dir = secondaries_dir(&path);
+            fs::create_dir_all(&dir).unwrap();
+            remove_stale_secondaries(&dir);
+
+            let mut opts = opts.clone();
+            // required by the secondary instance
+            opts.set_max_open_files(-1);
+            let secondary = dir.join(format!("{}-secondary", std::process::id()));
+            let db =
+                DB::open_cf_as_secondary(&opts, Path::new(&path), secondary.as_path(), CF_NAMES)
+                    .unwrap();
+            db.try_catch_up_with_primary().unwrap();
+            db
+        }
+    }
+}
+
+/// the indradb queries over the raw rocksdb, which has the layout of `RocksdbDatastore`.
+/// Nothing can be written, the writes fail with `Unsupported`
+pub struct ReadOnlyDatastore {
+    db: Arc<DB>,
+}
+
+impl Datastore for ReadOnlyDatastore {
+    type Transaction<'a>
+        = ReadOnlyTransaction<'a>
+    where
+        Self: 'a;
+
+    fn transaction(&'_ self) -> Self::Transaction<'_> {
+        ReadOnlyTransaction { db: &self.db }
+    }
+}
+
+pub struct ReadOnlyTransaction<'a> {
+    db: &'a DB,
+}
+
+/// the outbound id, type and inbound id, the key of `edge_ranges:v2`
+fn edge_key(edge: &Edge) -> Vec<u8> {
+    let mut key = edge.outbound_id.as_bytes().to_vec();
+    key.extend(rocks::encode_identifier(edge.t.as_str()));
+    key.extend(edge.inbound_id.as_bytes());
+    key
+}
+
+fn json(value: &[u8]) -> Json {
+    Json::new(serde_json::from_slice(value).unwrap())
+}
+
+impl<'a> ReadOnlyTransaction<'a> {
+    /// the rows of the column family from the key `from` on, while they start with `prefix`
+    fn rows(
+        &self,
+        cf_name: &str,
+        from: &[u8],
+        prefix: Vec<u8>,
+    ) -> impl Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a {
+        let db = self.db;
+        db.iterator_cf(
+            db.cf_handle(cf_name).unwrap(),
+            IteratorMode::From(from, Direction::Forward),
+        )
+        .map(|row| row.unwrap())
+        .take_while(move |(k, _)| k.starts_with(&prefix))
+    }
+
+    fn get(&self, cf_name: &str, key: &[u8]) -> Option<Vec<u8>> {
+        let cf = self.db.cf_handle(cf_name).unwrap();
+        self.db.get_cf(cf, key).unwrap()
+    }
+
+    fn is_indexed(&self, name: &Identifier) -> bool {
+        index::indexed_properties(self.db)
+            .iter()
+            .any(|n| n == name.as_str())
+    }
+
+    fn edges(&'a self, cf_name: &str, from: &[u8]) -> DynIter<'a, Edge> {
+        Box::new(self.rows(cf_name, from, Vec::new()).map(|(k, _)| {
+            let (first, t, second) = rocks::decode_edge_range_key(&k);
+            Ok(Edge::new(first, Identifier::new(t).unwrap(), second))
+        }))
+    }
+
+    /// the edges in the index of the property
+    fn indexed_edges(&self, name: &Identifier) -> impl Iterator<Item = Edge> + 'a {
+        let prefix = rocks::encode_identifier(name.as_str());
+        self.rows("edge_property_values:v2", &prefix, prefix.clone())
+            .map(|(k, _)| {
+                let (_, _, out_id, t, in_id) = rocks::decode_edge_property_value_key(&k);
+                Edge::new(out_id, Identifier::new(t).unwrap(), in_id)
+            })
+    }
+
+    /// the vertices in the index of the property
+    fn indexed_vertices(&self, name: &Identifier) -> impl Iterator<Item = Uuid> + 'a {
+        let prefix = rocks::encode_identifier(name.as_str());
+        self.rows("vertex_property_values:v2", &prefix, prefix.clone())
+            .map(|(k, _)| rocks::decode_vertex_property_value_key(&k).2)
+    }
+}
+
+impl<'a> Transaction<'a> for ReadOnlyTransaction<'a> {
+    fn vertex_count(&self) -> u64 {
+        self.rows("vertices:v2", &[], Vec::new()).count() as u64
+    }
+
+    fn all_vertices(&'a self) -> indradb::Result<DynIter<'a, Vertex>> {
+        self.range_vertices(Uuid::nil())
+    }
+
+    fn range_vertices(&'a self, offset: Uuid) -> indradb::Result<DynIter<'a, Vertex>> {
+        let iter = self
+            .rows("vertices:v2", offset.as_bytes(), Vec::new())
+            .map(|(k, v)| {
+                let t = Identifier::new(rocks::decode_vertex_value(&v)).unwrap();
+                Ok(Vertex::with_id(rocks::read_uuid(&k).0, t))
+            });
+        Ok(Box::new(iter))
+    }
+
+    fn specific_vertices(&'a self, ids: Vec<Uuid>) -> indradb::Result<DynIter<'a, Vertex>> {
+        let iter = ids.into_iter().filter_map(move |id| {
+            let value = self.get("vertices:v2", id.as_bytes())?;
+            let t = Identifier::new(rocks::decode_vertex_value(&value)).unwrap();
+            Some(Ok(Vertex::with_id(id, t)))
+        });
+        Ok(Box::new(iter))
+    }
+
+    fn vertex_ids_with_property(
+        &'a self,
+        name: Identifier,
+    ) -> indradb::Result<Option<DynIter<'a, Uuid>>> {
+        if !self.is_indexed(&name) {
+            return Ok(None);
+        }
+        Ok(Some(Box::new(self.indexed_vertices(&name).map(Ok))))
+    }
+
+    fn vertex_ids_with_property_value(
+        &'a self,
+        name: Identifier,
+        value: &Json,
+    ) -> indradb::Result<Option<DynIter<'a, Uuid>>> {
+        if !self.is_indexed(&name) {
+            return Ok(None);
+        }
+        // the keys only have the hash of the value, so the value itself is compared
+        let value = value.clone();
+        let iter = self.indexed_vertices(&name).filter_map(move |id| {
+            let mut key = id.as_bytes().to_vec();
+            key.extend(rocks::encode_identifier(name.as_str()));
+            let found = json(&self.get("vertex_properties:v2", &key)?);
+            (found.0 == value.0).then_some(Ok(id))
+        });
+        Ok(Some(Box::new(iter)))
+    }
+
+    fn edge_count(&self) -> u64 {
+        self.rows("edge_ranges:v2", &[], Vec::new()).count() as u64
+    }
+
+    fn all_edges(&'a self) -> indradb::Result<DynIter<'a, Edge>> {
+        Ok(self.edges("edge_ranges:v2", &[]))
+    }
+
+    fn range_edges(&'a self, offset: Edge) -> indradb::Result<DynIter<'a, Edge>> {
+        Ok(self.edges("edge_ranges:v2", &edge_key(&offset)))
+    }
+
+    fn range_reversed_edges(&'a self, offset: Edge) -> indradb::Result<DynIter<'a, Edge>> {
+        // kept reversed, as the offset is
+        Ok(self.edges("reversed_edge_ranges:v2", &edge_key(&offset)))
+    }
+
+    fn specific_edges(&'a self, edges: Vec<Edge>) -> indradb::Result<DynIter<'a, Edge>> {
+        let iter = edges
+            .into_iter()
+            .filter(move |e| self.get("edge_ranges:v2", &edge_key(e)).is_some())
+            .map(Ok);
+        Ok(Box::new(iter))
+    }
+
+    fn edges_with_property(
+        &'a self,
+        name: Identifier,
+    ) -> indradb::Result<Option<DynIter<'a, Edge>>> {
+        if !self.is_indexed(&name) {
+            return Ok(None);
+        }
+        Ok(Some(Box::new(self.indexed_edges(&name).map(Ok))))
+    }
+
+    fn edges_with_property_value(
+        &'a self,
+        name: Identifier,
+        value: &Json,
+    ) -> indradb::Result<Option<DynIter<'a, Edge>>> {
+        if !self.is_indexed(&name) {
+            return Ok(None);
+        }
+        let value = value.clone();
+        let iter = self.indexed_edges(&name).filter_map(move |e| {
+            let mut key = edge_key(&e);
+            key.extend(rocks::encode_identifier(name.as_str()));
+            let found = json(&self.get("edge_properties:v2", &key)?);
+            (found.0 == value.0).then_some(Ok(e))
+        });
+        Ok(Some(Box::new(iter)))
+    }
+
+    fn vertex_property(&self, vertex: &Vertex, name: Identifier) -> indradb::Result<Option<Json>> {
+        let mut key = vertex.id.as_bytes().to_vec();
+        key.extend(rocks::encode_identifier(name.as_str()));
+        Ok(self.get("vertex_properties:v2", &key).map(|v| json(&v)))
+    }
+
+    fn all_vertex_properties_for_vertex(
+        &'a self,
+        vertex: &Vertex,
+    ) -> indradb::Result<DynIter<'a, (Identifier, Json)>> {
+        let prefix = vertex.id.as_bytes().to_vec();
+        let iter = self
+            .rows("vertex_properties:v2", &prefix, prefix.clone())
+            .map(|(k, v)| {
+                let (_, name) = rocks::decode_vertex_property_key(&k);
+                Ok((Identifier::new(name).unwrap(), json(&v)))
+            });
+        Ok(Box::new(iter))
+    }
+
+    fn edge_property(&self, edge: &Edge, name: Identifier) -> indradb::Result<Option<Json>> {
+        let mut key = edge_key(edge);
+        key.extend(rocks::encode_identifier(name.as_str()));
+        Ok(self.get("edge_properties:v2", &key).map(|v| json(&v)))
+    }
+
+    fn all_edge_properties_for_edge(
+        &'a self,
+        edge: &Edge,
+    ) -> indradb::Result<DynIter<'a, (Identifier, Json)>> {
+        let prefix = edge_key(edge);
+        let iter = self
+            .rows("edge_properties:v2", &prefix, prefix.clone())
+            .map(|(k, v)| {
+                let (_, _, _, name) = rocks::decode_edge_property_key(&k);
+                Ok((Identifier::new(name).unwrap(), json(&v)))
+            });
+        Ok(Box::new(iter))
+    }
+
+    fn delete_vertices(&mut self, _: Vec<Vertex>) -> indradb::Result<()> {
+        Err(indradb::Error::Unsupported)
+    }
+
+    fn delete_edges(&mut self, _: Vec<Edge>) -> indradb::Result<()> {
+        Err(indradb::Error::Unsupported)
+    }
+
+    fn delete_vertex_properties(&mut self, _: Vec<(Uuid, Identifier)>) -> indradb::Result<()> {
+        Err(indradb::Error::Unsupported)
+    }
+
+    fn delete_edge_properties(&mut self, _: Vec<(Edge, Identifier)>) -> indradb::Result<()> {
+        Err(indradb::Error::Unsupported)
+    }
+
+    fn create_vertex(&mut self, _: &Vertex) -> indradb::Result<bool> {
+        Err(indradb::Error::Unsupported)
+    }
+
+    fn create_edge(&mut self, _: &Edge) -> indradb::Result<bool> {
+        Err(indradb::Error::Unsupported)
+    }
+
+    fn bulk_insert(&mut self, _: Vec<BulkInsertItem>) -> indradb::Result<()> {
+        Err(indradb::Error::Unsupported)
+    }
+
+    fn index_property(&mut self, _: Identifier) -> indradb::Result<()> {
+        Err(indradb::Error::Unsupported)
+    }
+
+    fn set_vertex_properties(
+        &mut self,
+        _: Vec<Uuid>,
+        _: Identifier,
+        _: &Json,
+    ) -> indradb::Result<()> {
+        Err(indradb::Error::Unsupported)
+    }
+
+    fn set_edge_properties(
+        &mut self,
+        _: Vec<Edge>,
+        _: Identifier,
+        _: &Json,
+    ) -> indradb::Result<()> {
+        Err(indradb::Error::Unsupported)
+    }
+}
@@ -1,39 +1,206 @@
-use std::{fs::File, str::FromStr, io::Write};
-
-use crate::{utils, eth_common::TransactionInfo};
-use bigdecimal::ToPrimitive;
-use ethers::utils::WEI_IN_ETHER;
-use hashbrown::HashSet;
+use std::{fs::{self, File}, str::FromStr, io::{BufWriter, Write}};
+
+use crate::{
+    cluster, community, degree,
+    eth_common::TransactionInfo,
+    feature,
+    store::{self, Store},
+    utils,
+};
+use bigdecimal::BigDecimal;
+use hashbrown::{HashMap, HashSet};
 use indradb::{
-    Database, Identifier, QueryExt, QueryOutputValue, RocksdbDatastore, SpecificVertexQuery, Vertex, SpecificEdgeQuery,
+    Database, Datastore, Edge, Identifier, QueryExt, QueryOutputValue, SpecificVertexQuery,
+    Vertex,
 };
 use rand::seq::SliceRandom;
 use rocksdb::Options;
 use uuid::Uuid;
 
-#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
 pub enum GraphType {
     CsvEdgelist,
     // CsvNodes, // TODO
     // Rdf, // TODO
 }
 
-#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+#[derive(clap::ValueEnum, serde::Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "kebab-case")]
 pub enum VType {
+    // kebab-case would split every capital, i.e. `e-t-h-address`
+    #[serde(rename = "eth-address")]
     ETHAddress,
     String,
 }
 
-#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+#[derive(clap::ValueEnum, serde::Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
 pub enum Direction {
     Out,
     In,
     Both,
 }
 
+/// Vertices which are recorded in the subgraph but never expanded,
+/// e.g. exchange hot wallets and popular routers
+pub struct Stopper {
+    ids: HashSet<Uuid>,
+    /// 0 means no degree limit
+    max_degree: u64,
+}
+
+impl Stopper {
+    pub fn new(stop_list: Option<String>, max_degree: u64, v_type: VType) -> Self {
+        let addrs: Vec<String> = match stop_list {
+            None => Vec::new(),
+            Some(stop_list) => fs::read_to_string(stop_list)
+                .unwrap()
+                .split_whitespace()
+                .map(|s| s.to_string())
+                .collect(),
+        };
+
+        Self::from_addrs(&addrs, max_degree, v_type)
+    }
+
+    pub fn from_addrs(addrs: &[String], max_degree: u64, v_type: VType) -> Self {
+        let ids: HashSet<Uuid> = addrs.iter().map(|addr| to_uuid(addr, v_type)).collect();
+        log::debug!("{} addresses in stop list", ids.len());
+
+        Stopper { ids, max_degree }
+    }
+
+    /// on the stop list
+    pub fn listed(&self, v: &Vertex) -> bool {
+        self.ids.contains(&v.id)
+    }
+
+    /// check the stop list first, then the edge range count on the crawling direction
+    pub fn should_stop<D: Datastore>(
+        &self,
+        db: &Database<D>,
+        v: &Vertex,
+        direction: Direction,
+    ) -> bool {
+        if self.listed(v) {
+            return true;
+        }
+        if self.max_degree == 0 {
+            return false;
+        }
+
+        // only the edge ranges of the crawling direction are counted
+        let degree = match direction {
+            Direction::Out => degree::out_degree(db, v.id),
+            Direction::In => degree::in_degree(db, v.id),
+            Direction::Both => degree::degree(db, v.id).degree,
+        };
+
+        degree > self.max_degree
+    }
+}
+
+pub fn to_uuid(addr: &str, v_type: VType) -> Uuid {
+    match v_type {
+        VType::ETHAddress => utils::addr_to_uuid(addr),
+        VType::String => utils::str_to_uuid(addr),
+    }
+}
+
+/// `to_uuid`, None when the address is malformed
+pub fn try_to_uuid(addr: &str, v_type: VType) -> Option<Uuid> {
+    match v_type {
+        VType::ETHAddress => utils::try_addr_to_uuid(addr),
+        VType::String => Some(utils::str_to_uuid(addr)),
+    }
+}
+
+/// the crawled subgraph, the edges are kept in the crawling order
+#[derive(Debug, Default)]
+pub struct Subgraph {
+    /// all seen vertices, including the stopped ones
+    pub vertices: HashMap<Uuid, Identifier>,
+    pub edges: Vec<Edge>,
+    pub stopped: HashSet<Uuid>,
+}
+
+impl Subgraph {
+    pub fn address(&self, id: &Uuid) -> &str {
+        self.vertices[id].as_str()
+    }
+
+    /// rename the clustered vertices to their cluster id and drop the edges inside a cluster
+    pub fn collapse(&mut self, clusters: &HashMap<Uuid, String>) {
+        for (id, cluster) in clusters {
+            self.vertices.insert(*id, Identifier::new(cluster).unwrap());
+        }
+        let vertices = &self.vertices;
+        self.edges
+            .retain(|e| vertices[&e.outbound_id] != vertices[&e.inbound_id]);
+    }
+}
+
+/// the receiver of the new edges of each crawled vertex, with all vertices seen so far
+pub type EdgeSink<'a> = &'a mut dyn FnMut(&HashMap<Uuid, Identifier>, &[Edge]);
+
+/// crawl the subgraph from the seeds in the direction, the stopped vertices are never expanded
+pub fn crawl<D: Datastore>(
+    db: &Database<D>,
+    seeds: &[Vertex],
+    hop: usize,
+    direction: Direction,
+    stopper: &Stopper,
+) -> Subgraph {
+    crawl_into(db, seeds, hop, direction, stopper, None)
+}
+
+/// `crawl`, but the edges go to the sink as they are found rather than into the subgraph
+pub fn crawl_into<D: Datastore>(
+    db: &Database<D>,
+    seeds: &[Vertex],
+    hop: usize,
+    direction: Direction,
+    stopper: &Stopper,
+    mut sink: Option<EdgeSink>,
+) -> Subgraph {
+    let mut subgraph = Subgraph::default();
+    let mut crawled_edges: HashSet<Identifier> = HashSet::new();
+    let mut crawled_vertices: HashSet<Identifier> = HashSet::new();
+
+    for v in seeds {
+        subgraph.vertices.insert(v.id, v.t);
+        if stopper.should_stop(db, v, direction) {
+            log::debug!("stop at the seed {}", v.t.as_str());
+            subgraph.stopped.insert(v.id);
+            continue;
+        }
+        run_hop(
+            db,
+            &mut subgraph,
+            hop,
+            v,
+            &mut crawled_edges,
+            &mut crawled_vertices,
+            direction,
+            stopper,
+            &mut sink,
+        );
+    }
+
+    log::debug!(
+        "{} vertices in subgraph, {} edges, {} stopped",
+        subgraph.vertices.len(),
+        subgraph.edges.len(),
+        subgraph.stopped.len()
+    );
+
+    subgraph
+}
+
 pub fn gen_subgraph(
     path: String,
     opts: &mut Options,
+    mode: store::OpenMode,
     v: &mut Vec<String>,
     hop: usize,
     output: String,
@@ -41,76 +208,278 @@ pub fn gen_subgraph(
     v_type: VType,
     direction: Direction,
     with_props: Vec<String>,
+    stopper: Stopper,
+    nodes_output: Option<String>,
+    collapse: bool,
+    communities: Option<community::Weight>,
+    exact: bool,
 ) {
-    opts.optimize_for_point_lookup(0x100000000);
-    opts.set_optimize_filters_for_hits(true);
-    opts.optimize_level_style_compaction(0x100000000);
-    opts.set_memtable_whole_key_filtering(true);
+    match store::open(path, opts, mode) {
+        Store::Primary(db) => write_subgraph(
+            &db,
+            v,
+            hop,
+            output,
+            graph_type,
+            v_type,
+            direction,
+            with_props,
+            stopper,
+            nodes_output,
+            collapse,
+            communities,
+            exact,
+        ),
+        Store::ReadOnly(db, _) => write_subgraph(
+            &db,
+            v,
+            hop,
+            output,
+            graph_type,
+            v_type,
+            direction,
+            with_props,
+            stopper,
+            nodes_output,
+            collapse,
+            communities,
+            exact,
+        ),
+    }
+}
 
-    let datastore = RocksdbDatastore::new_db_with_options(path, opts).unwrap();
+fn write_subgraph<D: Datastore>(
+    datastore: &Database<D>,
+    v: &mut Vec<String>,
+    hop: usize,
+    output: String,
+    graph_type: GraphType,
+    v_type: VType,
+    direction: Direction,
+    with_props: Vec<String>,
+    stopper: Stopper,
+    nodes_output: Option<String>,
+    collapse: bool,
+    communities: Option<community::Weight>,
+    exact: bool,
+) {
     // convert v to ids
     v.sort();
     v.dedup();
-    let ids: Vec<Uuid> = v
-        .iter()
-        .map(|addr| match v_type {
-            VType::ETHAddress => utils::addr_to_uuid(addr),
-            VType::String => utils::str_to_uuid(addr),
-        })
-        .collect();
+    let ids: Vec<Uuid> = v.iter().map(|addr| to_uuid(addr, v_type)).collect();
     log::debug!("{} addresses", ids.len());
 
     if with_props.len() > 0 {
         log::debug!("props: {:?}", with_props);
     }
 
-    // let mut output = csv::Writer::from_path(output).unwrap();
-    let mut output = File::create(output).unwrap();
-
     let q = SpecificVertexQuery::new(ids);
-    let result = datastore.get(q).unwrap();
-
-    for out_val in result {
-        if let QueryOutputValue::Vertices(vertices) = out_val {
-            log::debug!("{} vertices", vertices.len());
-
-            let mut crawled_edges: HashSet<Identifier> = HashSet::new();
-            let mut crawled_vertices: HashSet<Identifier> = HashSet::new();
-
-            for v in &vertices {
-                match graph_type {
-                    GraphType::CsvEdgelist => run_hop(
-                        &datastore,
-                        &mut output,
-                        hop,
-                        v,
-                        &mut crawled_edges,
-                        &mut crawled_vertices,
-                        direction,
-                        &with_props,
-                    ),
-                    _ => todo!(),
-                }
+    let vertices = indradb::util::extract_vertices(datastore.get(q).unwrap()).unwrap();
+    log::debug!("{} vertices", vertices.len());
+
+    let communities = match (communities, &nodes_output) {
+        (Some(weight), Some(_)) => Some(weight),
+        (Some(_), None) => {
+            log::warn!("the communities are only written into the node list");
+            None
+        }
+        _ => None,
+    };
+
+    let mut writer = match graph_type {
+        GraphType::CsvEdgelist => {
+            EdgelistWriter::create(datastore, output, !with_props.is_empty(), exact, collapse)
+        }
+    };
+    // the edges are only held for the community detection
+    let mut subgraph = if communities.is_some() {
+        let subgraph = crawl(datastore, &vertices, hop, direction, &stopper);
+        writer.write(&subgraph.vertices, &subgraph.edges);
+        subgraph
+    } else {
+        let mut sink = |vertices: &HashMap<Uuid, Identifier>, edges: &[Edge]| {
+            writer.write(vertices, edges)
+        };
+        crawl_into(datastore, &vertices, hop, direction, &stopper, Some(&mut sink))
+    };
+    writer.look_up(subgraph.vertices.keys().copied());
+    let clusters = writer.finish();
+    if collapse {
+        log::debug!("{} vertices in clusters", clusters.len());
+        subgraph.collapse(&clusters);
+    }
+
+    let communities =
+        communities.map(|weight| community::detect(datastore, &subgraph, weight));
+
+    if let Some(nodes_output) = nodes_output {
+        write_nodes(&subgraph, nodes_output, communities.as_ref());
+    }
+}
+
+/// the value in ether and the gas price in gwei, which are floats unless exact,
+/// the gas limit and the gas used are plain integers, the missing ones are null
+fn edge_attrs(tx: &TransactionInfo, exact: bool) -> serde_json::Map<String, serde_json::Value> {
+    let decimal = |d: BigDecimal| -> serde_json::Value {
+        if exact {
+            d.normalized().to_string().into()
+        } else {
+            utils::decimal_to_f64(&d).into()
+        }
+    };
+    let gas_price = tx.effective_gas_price.or(tx.gas_price);
+
+    let mut attrs = serde_json::Map::new();
+    attrs.insert("block_number".to_owned(), tx.block_number.map(|b| b.as_u64()).into());
+    attrs.insert("value".to_owned(), decimal(utils::wei_to_ether(tx.value)));
+    attrs.insert("gas".to_owned(), tx.gas.as_u64().into());
+    attrs.insert("gas_price".to_owned(), gas_price.map(utils::wei_to_gwei).map(decimal).into());
+    attrs.insert("gas_used".to_owned(), tx.gas_used.map(|g| g.as_u64()).into());
+    attrs
+}
+
+/// writes the edges as they are crawled, so the plain export doesn't hold the whole subgraph
+struct EdgelistWriter<'a, D: Datastore> {
+    db: &'a Database<D>,
+    output: BufWriter<File>,
+    with_props: bool,
+    exact: bool,
+    /// the cluster ids of the looked up vertices, None unless collapsing
+    clusters: Option<HashMap<Uuid, String>>,
+    looked_up: HashSet<Uuid>,
+    written: usize,
+    /// written with empty props, e.g. the edges loaded from an edge list
+    without_details: usize,
+}
+
+impl<'a, D: Datastore> EdgelistWriter<'a, D> {
+    fn create(
+        db: &'a Database<D>,
+        output: String,
+        with_props: bool,
+        exact: bool,
+        collapse: bool,
+    ) -> Self {
+        EdgelistWriter {
+            db,
+            output: BufWriter::new(File::create(output).unwrap()),
+            with_props,
+            exact,
+            clusters: collapse.then(HashMap::new),
+            looked_up: HashSet::new(),
+            written: 0,
+            without_details: 0,
+        }
+    }
+
+    /// look up the clusters of the vertices which haven't been yet
+    fn look_up<I: IntoIterator<Item = Uuid>>(&mut self, ids: I) {
+        let clusters = match &mut self.clusters {
+            Some(clusters) => clusters,
+            None => return,
+        };
+        let looked_up = &mut self.looked_up;
+        let ids: Vec<Uuid> = ids.into_iter().filter(|id| looked_up.insert(*id)).collect();
+        clusters.extend(cluster::get_clusters(self.db, ids));
+    }
+
+    fn name<'b>(&'b self, vertices: &'b HashMap<Uuid, Identifier>, id: &Uuid) -> &'b str {
+        match self.clusters.as_ref().and_then(|clusters| clusters.get(id)) {
+            Some(cluster) => cluster.as_str(),
+            None => vertices[id].as_str(),
+        }
+    }
+
+    fn write(&mut self, vertices: &HashMap<Uuid, Identifier>, edges: &[Edge]) {
+        self.look_up(edges.iter().flat_map(|e| [e.outbound_id, e.inbound_id]));
+        let mut details: HashMap<Edge, TransactionInfo> = if self.with_props {
+            feature::fetch_edge_details(self.db, edges.to_vec())
+                .into_iter()
+                .collect()
+        } else {
+            HashMap::new()
+        };
+
+        for e in edges {
+            let last = if !self.with_props {
+                e.t.as_str().to_owned()
+            } else if let Some(tx) = details.remove(e) {
+                serde_json::to_string(&edge_attrs(&tx, self.exact)).unwrap()
+            } else {
+                self.without_details += 1;
+                "{}".to_owned()
+            };
+
+            let from = self.name(vertices, &e.outbound_id);
+            let to = self.name(vertices, &e.inbound_id);
+            // the edges inside a cluster
+            if self.clusters.is_some() && from == to {
+                continue;
             }
+            let line = vec![from, to, last.as_str()].join(" ") + "\n";
+            self.output.write_all(line.as_bytes()).unwrap();
+            self.written += 1;
+        }
+    }
+
+    /// the looked up clusters
+    fn finish(mut self) -> HashMap<Uuid, String> {
+        self.output.flush().unwrap();
+        log::debug!("{} edges written", self.written);
+        if self.without_details > 0 {
+            log::warn!(
+                "{} edges have no details, written with empty props",
+                self.without_details
+            );
+        }
+        self.clusters.unwrap_or_default()
+    }
+}
+
+fn write_nodes(
+    subgraph: &Subgraph,
+    nodes_output: String,
+    communities: Option<&HashMap<String, usize>>,
+) {
+    let mut nodes_output = csv::Writer::from_path(nodes_output).unwrap();
+    let mut header = vec!["address", "stopped"];
+    if communities.is_some() {
+        header.push("community");
+    }
+    nodes_output.write_record(header).unwrap();
+    // the collapsed clusters are written once
+    let mut written = HashSet::new();
+    for (id, t) in &subgraph.vertices {
+        if !written.insert(t) {
+            continue;
         }
+        let stopped = subgraph.stopped.contains(id);
+        let mut record = vec![t.as_str().to_owned(), stopped.to_string()];
+        if let Some(communities) = communities {
+            record.push(communities[t.as_str()].to_string());
+        }
+        nodes_output.write_record(record).unwrap();
     }
+    nodes_output.flush().unwrap();
 }
 
-fn run_hop(
-    db: &Database<RocksdbDatastore>,
-    output: &mut File,
+fn run_hop<D: Datastore>(
+    db: &Database<D>,
+    subgraph: &mut Subgraph,
     hop: usize,
     v: &Vertex,
     crawled_edges: &mut HashSet<Identifier>,
     crawled_vertices: &mut HashSet<Identifier>,
     direction: Direction,
-    with_props: &Vec<String>,
+    stopper: &Stopper,
+    sink: &mut Option<EdgeSink>,
 ) {
     if hop == 0 {
         return;
     };
     // log::debug!("{:?}", v);
     let mut next_hop_vertices: Vec<Vertex> = Vec::new();
+    let mut new_edges: Vec<Edge> = Vec::new();
 
     if direction == Direction::Both || direction == Direction::Out {
         let out_q = SpecificVertexQuery::single(v.id).outbound().unwrap();
@@ -138,26 +507,8 @@ fn run_hop(
 
                     if let QueryOutputValue::Vertices(tos) = result {
                         let to = &tos[0];
-                        if with_props.len() > 0 {
-                            let q = SpecificEdgeQuery::single(e.clone()).properties().unwrap();
-                            let properties =
-                                indradb::util::extract_edge_properties(db.get(q).unwrap()).unwrap();
-                            let property = &properties[0].props[0];
-                            let val = property.value.0.as_ref();
-                            let tx: TransactionInfo = serde_json::from_value(val.clone()).unwrap();
-                            let mut attrs = serde_json::Map::with_capacity(with_props.len());
-                            attrs.insert("block_number".to_owned(), tx.block_number.unwrap().as_u64().into());
-                            attrs.insert("value".to_owned(),  (utils::u256_to_bigdecimal(tx.value) / utils::u256_to_bigdecimal(WEI_IN_ETHER)).to_f64().into());
-                            attrs.insert("gas".to_owned(),  (utils::u256_to_bigdecimal(tx.gas) / utils::u256_to_bigdecimal(WEI_IN_ETHER)).to_f64().into());
-                            attrs.insert("gas_price".to_owned(),  (utils::u256_to_bigdecimal(tx.gas) / utils::u256_to_bigdecimal(WEI_IN_ETHER)).to_f64().into());
-                            attrs.insert("gas_used".to_owned(),  (utils::u256_to_bigdecimal(tx.gas_used.unwrap()) / utils::u256_to_bigdecimal(WEI_IN_ETHER)).to_f64().into());
-
-                            // output.write_record([from, to.t.as_str(), serde_json::to_string(&attrs).unwrap().as_str()]).unwrap();
-                            output.write_all((vec![from, &to.t,  serde_json::to_string(&attrs).unwrap().as_str()].join(" ") + "\n").as_bytes()).unwrap();
-                        } else {
-                            // output.write_record([from, to.t.as_str(), ]).unwrap();
-                            output.write_all((vec![from, &to.t,  &e.t].join(" ") + "\n").as_bytes()).unwrap();
-                        }
+                        subgraph.vertices.insert(to.id, to.t);
+                        new_edges.push(e);
 
                         next_hop_vertices.push(to.to_owned());
                     }
@@ -192,27 +543,8 @@ fn run_hop(
 
                     if let QueryOutputValue::Vertices(froms) = result {
                         let from = &froms[0]; // must only one
-
-                        if with_props.len() > 0 {
-                            let q = SpecificEdgeQuery::single(e.clone()).properties().unwrap();
-                            let properties =
-                                indradb::util::extract_edge_properties(db.get(q).unwrap()).unwrap();
-                            let property = &properties[0].props[0];
-                            let val = property.value.0.as_ref();
-                            let tx: TransactionInfo = serde_json::from_value(val.clone()).unwrap();
-                            let mut attrs = serde_json::Map::with_capacity(with_props.len());
-                            attrs.insert("blockNumber".to_owned(), tx.block_number.unwrap().as_u64().into());
-                            attrs.insert("value".to_owned(),  (utils::u256_to_bigdecimal(tx.value) / utils::u256_to_bigdecimal(WEI_IN_ETHER)).to_f64().into());
-                            attrs.insert("gas".to_owned(),  (utils::u256_to_bigdecimal(tx.gas) / utils::u256_to_bigdecimal(WEI_IN_ETHER)).to_f64().into());
-                            attrs.insert("gas_price".to_owned(),  (utils::u256_to_bigdecimal(tx.gas) / utils::u256_to_bigdecimal(WEI_IN_ETHER)).to_f64().into());
-                            attrs.insert("gas_used".to_owned(),  (utils::u256_to_bigdecimal(tx.gas_used.unwrap()) / utils::u256_to_bigdecimal(WEI_IN_ETHER)).to_f64().into());
-                            
-                            // output.write_record([from.t.as_str(), to, serde_json::to_string(&attrs).unwrap().as_str()]).unwrap();
-                            output.write_all((vec![from.t.as_str() , to,  serde_json::to_string(&attrs).unwrap().as_str()].join(" ") + "\n").as_bytes()).unwrap();
-                        } else {
-                            // output.write_record([from.t.as_str(), to, &e.t]).unwrap();
-                            output.write_all((vec![from.t.as_str() , to, &e.t].join(" ") + "\n").as_bytes()).unwrap();
-                        } 
+                        subgraph.vertices.insert(from.id, from.t);
+                        new_edges.push(e);
 
                         next_hop_vertices.push(from.to_owned());
                     }
@@ -221,20 +553,56 @@ fn run_hop(
         }
     }
 
+    match sink {
+        Some(sink) => sink(&subgraph.vertices, &new_edges),
+        None => subgraph.edges.extend(new_edges),
+    }
+
     for next_v in next_hop_vertices {
         if crawled_vertices.contains(&next_v.t) {
             continue;
         }
         crawled_vertices.insert(next_v.t);
+
+        // the last hop expands nothing, so only the listed boundary vertices are marked
+        // rather than counting the degree of each
+        let stopped = if hop == 1 {
+            stopper.listed(&next_v)
+        } else {
+            stopper.should_stop(db, &next_v, direction)
+        };
+        if stopped {
+            log::debug!("hop {}: stop at {}", hop, next_v.t.as_str());
+            subgraph.stopped.insert(next_v.id);
+            continue;
+        }
+
         run_hop(
             db,
-            output,
+            subgraph,
             hop - 1,
             &next_v,
             crawled_edges,
             crawled_vertices,
             direction,
-            with_props,
+            stopper,
+            sink,
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_v_type() {
+        let v_type: VType = serde_json::from_str("\"eth-address\"").unwrap();
+        assert!(matches!(v_type, VType::ETHAddress));
+        assert!(serde_json::from_str::<VType>("\"e-t-h-address\"").is_err());
+
+        assert!(try_to_uuid("0xde0b295669a9fd93d5f28d9ec85e40f4cb697bae", v_type).is_some());
+        assert!(try_to_uuid("0xde0b", v_type).is_none());
+        assert!(try_to_uuid("0xde0b", VType::String).is_some());
+    }
+}
@@ -0,0 +1,320 @@
+use std::{collections::HashMap, fs};
+
+use rocksdb::{BlockBasedOptions, Cache, DBCompactionStyle, DBCompressionType, Options};
+use serde::Deserialize;
+
+/// the files kept free for the sockets, logs and outputs when capping `max_open_files`
+const RESERVED_FILES: i32 = 256;
+
+/// the levels of the lsm tree, `compression` has one entry per level
+const NUM_LEVELS: usize = 4;
+
+/// a preset of the rocksdb options for a kind of workload
+#[derive(clap::ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum Profile {
+    /// large write buffers and no auto compaction, for load and link
+    BulkLoad,
+    /// a large block cache and bloom filters, for the subgraph and feature queries
+    PointQuery,
+    /// readahead and a small cache which the scans don't pollute
+    Scan,
+    /// small caches, buffers and file handles
+    LowMemory,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    Snappy,
+    Lz4,
+    Zstd,
+}
+
+impl From<Compression> for DBCompressionType {
+    fn from(c: Compression) -> Self {
+        match c {
+            Compression::None => DBCompressionType::None,
+            Compression::Snappy => DBCompressionType::Snappy,
+            Compression::Lz4 => DBCompressionType::Lz4,
+            Compression::Zstd => DBCompressionType::Zstd,
+        }
+    }
+}
+
+/// the tunable options, every field is optional in the config file
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Tuning {
+    /// the block cache in bytes
+    pub block_cache: Option<usize>,
+    /// the bloom filter bits per key, 0 means no filter
+    pub bloom_bits: Option<f64>,
+    /// the compression of each level, the last one is repeated on the deeper levels
+    pub compression: Option<Vec<Compression>>,
+    /// -1 means keeping every file open, it is capped by the open files limit anyway
+    pub max_open_files: Option<i32>,
+    /// the memtable size in bytes
+    pub write_buffer_size: Option<usize>,
+    pub max_write_buffer_number: Option<i32>,
+    /// the background threads, 0 means the cpu count
+    pub parallelism: Option<i32>,
+    pub disable_auto_compactions: Option<bool>,
+}
+
+impl Tuning {
+    /// fill the unset fields from the other
+    fn or(self, other: Tuning) -> Tuning {
+        Tuning {
+            block_cache: self.block_cache.or(other.block_cache),
+            bloom_bits: self.bloom_bits.or(other.bloom_bits),
+            compression: self.compression.or(other.compression),
+            max_open_files: self.max_open_files.or(other.max_open_files),
+            write_buffer_size: self.write_buffer_size.or(other.write_buffer_size),
+            max_write_buffer_number: self
+                .max_write_buffer_number
+                .or(other.max_write_buffer_number),
+            parallelism: self.parallelism.or(other.parallelism),
+            disable_auto_compactions: self
+                .disable_auto_compactions
+                .or(other.disable_auto_compactions),
+        }
+    }
+}
+
+impl Profile {
+    pub fn tuning(self) -> Tuning {
+        use Compression::{Lz4, Zstd};
+        let none = Compression::None;
+        match self {
+            Profile::BulkLoad => Tuning {
+                block_cache: Some(0x4000000), // 64mb
+                bloom_bits: Some(10.0),
+                compression: Some(vec![none, none, Lz4, Zstd]),
+                max_open_files: Some(5_000),
+                write_buffer_size: Some(0x80000000), // 2G
+                max_write_buffer_number: Some(3),
+                parallelism: Some(0),
+                disable_auto_compactions: Some(true),
+            },
+            Profile::PointQuery => Tuning {
+                block_cache: Some(0x100000000), // 4G
+                bloom_bits: Some(10.0),
+                compression: Some(vec![none, Lz4, Lz4, Zstd]),
+                max_open_files: Some(-1),
+                write_buffer_size: Some(0x4000000), // 64mb
+                max_write_buffer_number: Some(3),
+                parallelism: Some(0),
+                disable_auto_compactions: Some(false),
+            },
+            Profile::Scan => Tuning {
+                block_cache: Some(0x10000000), // 256mb
+                bloom_bits: Some(10.0),
+                compression: Some(vec![none, Lz4, Lz4, Zstd]),
+                max_open_files: Some(5_000),
+                write_buffer_size: Some(0x4000000), // 64mb
+                max_write_buffer_number: Some(3),
+                parallelism: Some(0),
+                disable_auto_compactions: Some(false),
+            },
+            Profile::LowMemory => Tuning {
+                block_cache: Some(0x2000000), // 32mb
+                bloom_bits: Some(10.0),
+                compression: Some(vec![Lz4, Lz4, Zstd, Zstd]),
+                max_open_files: Some(512),
+                write_buffer_size: Some(0x1000000), // 16mb
+                max_write_buffer_number: Some(2),
+                parallelism: Some(2),
+                disable_auto_compactions: Some(false),
+            },
+        }
+    }
+}
+
+/// the config file, the top level values apply to every profile and the tables to the named one, e.g.
+/// `profile = "point-query"`, `block_cache = 8589934592` and `[profiles.bulk-load]` with `write_buffer_size = 1073741824`
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// replaces the default profile of the command
+    pub profile: Option<Profile>,
+    #[serde(flatten)]
+    pub tuning: Tuning,
+    #[serde(default)]
+    pub profiles: HashMap<Profile, Tuning>,
+}
+
+impl Config {
+    pub fn load(file: &str) -> Config {
+        let content = fs::read_to_string(file).unwrap();
+        match toml::from_str(&content) {
+            Ok(config) => config,
+            Err(e) => {
+                log::error!("invalid config {}: {}", file, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    /// the tuning of the profile, the config values take precedence over the preset
+    pub fn tuning(&self, profile: Profile) -> Tuning {
+        let named = self.profiles.get(&profile).cloned().unwrap_or_default();
+        named.or(self.tuning.clone()).or(profile.tuning())
+    }
+}
+
+/// the soft limit in `/proc/self/limits`
+fn parse_open_files_limit(limits: &str) -> Option<i32> {
+    let line = limits.lines().find(|l| l.starts_with("Max open files"))?;
+    let soft = line["Max open files".len()..].split_whitespace().next()?;
+    // "unlimited" has no cap
+    soft.parse::<i64>()
+        .ok()
+        .map(|n| n.min(i32::MAX as i64) as i32)
+}
+
+/// `max_open_files` within the open files limit, rather than failing with "Too many open files"
+fn cap_open_files(max_open_files: i32, limit: Option<i32>) -> i32 {
+    let cap = match limit {
+        Some(limit) => (limit - RESERVED_FILES).max(RESERVED_FILES),
+        None => return max_open_files,
+    };
+    if max_open_files < 0 || max_open_files > cap {
+        log::warn!(
+            "max_open_files {} capped to {} by the open files limit, raise it with `ulimit -n`",
+            max_open_files,
+            cap
+        );
+        return cap;
+    }
+    max_open_files
+}
+
+/// the options of the profile, shared by every command
+pub fn options(profile: Profile, tuning: &Tuning) -> Options {
+    let t = tuning.clone().or(profile.tuning());
+    log::debug!("{:?}: {:?}", profile, t);
+
+    let mut opts = Options::default();
+    if profile == Profile::BulkLoad {
+        // before the rest, it overrides the compaction triggers
+        opts.prepare_for_bulk_load();
+    }
+    opts.create_if_missing(true);
+
+    let parallelism = match t.parallelism.unwrap() {
+        0 => num_cpus::get().try_into().unwrap(),
+        n => n,
+    };
+    opts.increase_parallelism(parallelism);
+    opts.set_compaction_style(DBCompactionStyle::Level);
+    opts.set_target_file_size_base(0x4000000); // 64mb
+    opts.set_num_levels(NUM_LEVELS as i32);
+    opts.set_max_bytes_for_level_base(536_870_912); // 512mb
+    opts.set_max_bytes_for_level_multiplier(8.0);
+    if profile != Profile::BulkLoad {
+        opts.set_level_zero_file_num_compaction_trigger(8);
+        opts.set_level_zero_slowdown_writes_trigger(17);
+        opts.set_level_zero_stop_writes_trigger(24);
+    }
+
+    opts.set_write_buffer_size(t.write_buffer_size.unwrap());
+    opts.set_max_write_buffer_number(t.max_write_buffer_number.unwrap());
+    opts.set_disable_auto_compactions(t.disable_auto_compactions.unwrap());
+
+    let mut levels: Vec<DBCompressionType> = t
+        .compression
+        .unwrap()
+        .into_iter()
+        .map(DBCompressionType::from)
+        .collect();
+    let last = *levels.last().unwrap_or(&DBCompressionType::None);
+    levels.resize(NUM_LEVELS, last);
+    opts.set_compression_per_level(&levels);
+
+    let limit = fs::read_to_string("/proc/self/limits")
+        .ok()
+        .and_then(|limits| parse_open_files_limit(&limits));
+    opts.set_max_open_files(cap_open_files(t.max_open_files.unwrap(), limit));
+
+    let mut table = BlockBasedOptions::default();
+    table.set_block_cache(&Cache::new_lru_cache(t.block_cache.unwrap()));
+    let bloom_bits = t.bloom_bits.unwrap();
+    if bloom_bits > 0.0 {
+        table.set_bloom_filter(bloom_bits, false);
+    }
+    match profile {
+        Profile::PointQuery => {
+            table.set_cache_index_and_filter_blocks(true);
+            table.set_pin_l0_filter_and_index_blocks_in_cache(true);
+            opts.set_optimize_filters_for_hits(true);
+            opts.set_memtable_whole_key_filtering(true);
+        }
+        Profile::Scan => {
+            opts.set_advise_random_on_open(false);
+            opts.set_compaction_readahead_size(0x200000); // 2mb
+        }
+        Profile::LowMemory => {
+            // the index and filter blocks count against the cache instead of growing with the data
+            table.set_cache_index_and_filter_blocks(true);
+        }
+        Profile::BulkLoad => {}
+    }
+    opts.set_block_based_table_factory(&table);
+
+    // opts.set_enable_blob_files(true);
+    // opts.set_min_blob_size(0x0); // all in blob
+    opts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_precedence() {
+        let config: Config = toml::from_str(
+            r#"
+            profile = "scan"
+            block_cache = 1024
+            max_open_files = 100
+
+            [profiles.bulk-load]
+            block_cache = 2048
+            compression = ["zstd"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.profile, Some(Profile::Scan));
+
+        let scan = config.tuning(Profile::Scan);
+        assert_eq!(scan.block_cache, Some(1024));
+        assert_eq!(scan.max_open_files, Some(100));
+        assert_eq!(scan.bloom_bits, Profile::Scan.tuning().bloom_bits);
+
+        let bulk = config.tuning(Profile::BulkLoad);
+        assert_eq!(bulk.block_cache, Some(2048));
+        assert_eq!(bulk.compression, Some(vec![Compression::Zstd]));
+        assert_eq!(bulk.max_open_files, Some(100));
+        assert_eq!(bulk.disable_auto_compactions, Some(true));
+
+        assert!(toml::from_str::<Config>("block_size = 1").is_err());
+    }
+
+    #[test]
+    fn test_open_files_limit() {
+        let limits = "Limit                     Soft Limit           Hard Limit           Units     \n\
+                      Max processes             63459                63459                processes \n\
+                      Max open files            1024                 524288               files     \n";
+        assert_eq!(parse_open_files_limit(limits), Some(1024));
+        let unlimited =
+            "Max open files            unlimited            unlimited            files     \n";
+        assert_eq!(parse_open_files_limit(unlimited), None);
+
+        assert_eq!(cap_open_files(-1, Some(1024)), 768);
+        assert_eq!(cap_open_files(5_000, Some(65535)), 5_000);
+        assert_eq!(cap_open_files(5_000, Some(300)), RESERVED_FILES);
+        assert_eq!(cap_open_files(-1, None), -1);
+    }
+}
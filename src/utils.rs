@@ -1,6 +1,9 @@
 use std::str::FromStr;
 
-use bigdecimal::BigDecimal;
+use bigdecimal::{
+    num_bigint::{BigInt, Sign},
+    BigDecimal, ToPrimitive,
+};
 use ethers::types::{Address, U256, H160};
 use uuid::Uuid;
 
@@ -10,6 +13,11 @@ pub fn addr_to_uuid(addr: &str) -> Uuid {
     id
 }
 
+/// `addr_to_uuid` of the user input, None when it isn't an address
+pub fn try_addr_to_uuid(addr: &str) -> Option<Uuid> {
+    Address::from_str(addr).ok().map(|address| h160_to_uuid(&address))
+}
+
 pub fn h160_to_uuid(address: &H160) -> Uuid {
     let id = Uuid::new_v5(&Uuid::NAMESPACE_OID, address.as_bytes()); // sha1 hash = 16bytes
     id
@@ -19,8 +27,45 @@ pub fn str_to_uuid(str: &str) -> Uuid {
     Uuid::new_v5(&Uuid::NAMESPACE_OID, str.as_bytes())
 }
 
+pub const GWEI_DECIMALS: i64 = 9;
+pub const ETHER_DECIMALS: i64 = 18;
+
+pub fn u256_to_bigint(u256: U256) -> BigInt {
+    let mut bytes = [0u8; 32];
+    u256.to_big_endian(&mut bytes);
+    BigInt::from_bytes_be(Sign::Plus, &bytes)
+}
+
 pub fn u256_to_bigdecimal(u256: U256) -> BigDecimal {
-    BigDecimal::from_str(&u256.to_string()).unwrap()
+    BigDecimal::new(u256_to_bigint(u256), 0)
+}
+
+/// exact, the wei amount is only rescaled
+pub fn wei_to_gwei(wei: U256) -> BigDecimal {
+    BigDecimal::new(u256_to_bigint(wei), GWEI_DECIMALS)
+}
+
+/// exact, the wei amount is only rescaled
+pub fn wei_to_ether(wei: U256) -> BigDecimal {
+    BigDecimal::new(u256_to_bigint(wei), ETHER_DECIMALS)
+}
+
+/// exact sum of the wei amounts, which may overflow U256
+pub fn sum_wei(list: &[U256]) -> BigInt {
+    list.iter().map(|wei| u256_to_bigint(*wei)).sum()
+}
+
+/// the exact mean, or None on the empty list
+pub fn avg_wei(list: &[U256]) -> Option<BigDecimal> {
+    if list.is_empty() {
+        return None;
+    }
+    Some(BigDecimal::new(sum_wei(list), 0) / BigDecimal::from(list.len() as u64))
+}
+
+/// the only place turning the exact decimal into float
+pub fn decimal_to_f64(d: &BigDecimal) -> f64 {
+    d.to_f64().unwrap_or(f64::NAN)
 }
 
 pub fn gini(v: &Vec<f64>) -> f64 {
@@ -36,6 +81,23 @@ pub fn gini(v: &Vec<f64>) -> f64 {
     ((n + 1.) - (2. * sum) / cumx[cumx.len() - 1]) / n
 }
 
+/// NaN on the empty list
+pub fn mean(v: &[f64]) -> f64 {
+    if v.is_empty() {
+        return f64::NAN;
+    }
+    v.iter().sum::<f64>() / v.len() as f64
+}
+
+/// the population standard deviation, NaN on the empty list
+pub fn std(v: &[f64]) -> f64 {
+    let mean = mean(v);
+    if mean.is_nan() {
+        return f64::NAN;
+    }
+    (v.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / v.len() as f64).sqrt()
+}
+
 fn cumsum(v: Vec<f64>) -> Vec<f64> {
     v.into_iter()
         .scan(0.0, |acc, x| {
@@ -53,8 +115,6 @@ fn cumsum(v: Vec<f64>) -> Vec<f64> {
 
 #[cfg(test)]
 mod tests {
-    use bigdecimal::ToPrimitive;
-
     // Note this useful idiom: importing names from outer (for mod tests) scope.
     use super::*;
 
@@ -65,6 +125,27 @@ mod tests {
         assert_eq!((a / b).to_f64().unwrap(), 1.5);
     }
 
+    #[test]
+    fn test_wei() {
+        let whale = U256::from_dec_str("123456789012345678901234567").unwrap();
+        assert_eq!(
+            wei_to_ether(whale),
+            BigDecimal::from_str("123456789.012345678901234567").unwrap()
+        );
+        assert_eq!(
+            wei_to_gwei(whale),
+            BigDecimal::from_str("123456789012345678.901234567").unwrap()
+        );
+
+        let sum = sum_wei(&[U256::MAX, U256::one()]);
+        assert_eq!(sum, u256_to_bigint(U256::MAX) + 1);
+        assert_eq!(
+            avg_wei(&[U256::from(1), U256::from(2)]).unwrap(),
+            BigDecimal::from_str("1.5").unwrap()
+        );
+        assert_eq!(avg_wei(&[]), None);
+    }
+
     #[test]
     fn test_gini() {
         let i = gini(&vec![50., 50., 70., 70., 70., 90., 150., 150., 150., 150.]);